@@ -0,0 +1,41 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use csussus::lexer;
+
+const CODE: &str = include_str!("../Cඞඞ.sus");
+const MISC: &str = include_str!("../tests/corpus/misc.sus");
+
+fn repeat(code: &str, times: usize) -> String {
+    let mut big = String::with_capacity(code.len() * times);
+    for _ in 0..times {
+        big.push_str(code);
+        big.push('\n');
+    }
+    big
+}
+
+// Dispatching operators on their first byte instead of matching two and one
+// byte slices in turn made lexing about 1.8x as fast for Cඞඞ.sus and 1.25x as
+// fast for misc.sus. Absolute times depend on the machine, so compare runs
+// with `cargo bench -- --save-baseline <name>` and `--baseline <name>`.
+fn bench_lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+
+    let code = repeat(CODE, 1000);
+    group.throughput(Throughput::Bytes(code.len() as u64));
+    group.bench_function("Cඞඞ.sus x1000", |b| {
+        b.iter(|| lexer::lex("bench", black_box(&code)))
+    });
+
+    let misc = repeat(MISC, 1000);
+    group.throughput(Throughput::Bytes(misc.len() as u64));
+    group.bench_function("misc.sus x1000", |b| {
+        b.iter(|| lexer::lex("bench", black_box(&misc)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_lex);
+criterion_main!(benches);
@@ -22,6 +22,9 @@ mod unix {
     #[cfg(target_os = "macos")]
     const MAP_ANONYMOUS: c_int = 0x1000;
 
+    #[cfg(target_os = "linux")]
+    const MREMAP_MAYMOVE: c_int = 1;
+
     #[cfg(target_os = "linux")]
     const SC_PAGE_SIZE: c_int = 30;
     #[cfg(target_os = "macos")]
@@ -39,6 +42,14 @@ mod unix {
 
         pub fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
         pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn mremap(
+            old_address: *mut c_void,
+            old_size: usize,
+            new_size: usize,
+            flags: c_int,
+            ...
+        ) -> *mut c_void;
 
         pub fn sysconf(name: c_int) -> c_long;
     }
@@ -68,6 +79,28 @@ mod unix {
         mprotect(addr as _, size_aligned, PROT_READ | PROT_WRITE);
     }
 
+    /// Resizes a mapping, possibly moving it. The whole mapping must have the
+    /// same protection, otherwise the kernel refuses to remap it.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn vm_grow(
+        addr: *mut u8,
+        old_size_aligned: usize,
+        new_size_aligned: usize,
+    ) -> *mut u8 {
+        let remapped = mremap(
+            addr as _,
+            old_size_aligned,
+            new_size_aligned,
+            MREMAP_MAYMOVE,
+        ) as *mut u8;
+
+        if remapped as usize == !0 {
+            panic!("vm_grow: mremap failed");
+        }
+
+        remapped
+    }
+
     pub unsafe fn vm_uncommit(addr: *mut u8, size_aligned: usize) {
         mprotect(addr as _, size_aligned, PROT_NONE);
     }
@@ -175,12 +208,15 @@ mod windows {
 compile_error!("Operating system not supported");
 
 use std::{
+    alloc::Layout,
     cell::{Cell, OnceCell},
+    ffi::c_char,
     fmt::{self, Debug},
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash},
     marker::PhantomData,
-    mem,
-    ops::{Index, IndexMut},
-    slice,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut, Index, IndexMut},
+    ptr, slice,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
@@ -204,50 +240,151 @@ fn page_size() -> usize {
 
 const PAGES_PER_COMMIT: usize = 16;
 
+/// Byte written over freed allocations in debug builds.
+#[cfg(debug_assertions)]
+const POISON: u8 = 0xDE;
+
 pub const KIB: usize = 1024;
 pub const MIB: usize = 1024 * KIB;
 pub const GIB: usize = 1024 * MIB;
 pub const TIB: usize = 1024 * GIB;
 
 pub struct Arena {
-    base_addr: *mut u8,
-    end_addr: *mut u8,
+    /// Null until the address space is reserved
+    base_addr: Cell<*mut u8>,
+    end_addr: Cell<*mut u8>,
+    addr_space_size: usize,
     page_size: usize,
     uncommitted_addr: Cell<*mut u8>,
     bump_addr: Cell<*mut u8>,
+    /// Start address and size of the most recent allocation
+    last_alloc: Cell<(*mut u8, usize)>,
+    /// Whether the address space is remapped once it runs out, see
+    /// [`Arena::growable`]
+    growable: bool,
+    /// Most bytes that were ever allocated at once
+    peak_used: Cell<usize>,
+    /// Set while [`Arena::alloc_region`] is updating the bump pointer
+    #[cfg(debug_assertions)]
+    allocating: Cell<bool>,
 }
 
 impl Arena {
     pub fn new(addr_space_size: usize) -> Self {
-        unsafe {
-            let page_size = page_size();
-            let addr_space_size = ceil_align(addr_space_size, page_size);
+        let arena = Self::lazy(addr_space_size);
+        unsafe { arena.reserve() };
+        arena
+    }
 
-            let base_addr = vm_reserve(addr_space_size);
-            let end_addr = base_addr.byte_add(addr_space_size);
-            let uncommitted_addr = Cell::new(base_addr);
-            let bump_addr = Cell::new(base_addr);
+    /// Creates an arena that only reserves its address space once something
+    /// is allocated in it.
+    pub fn lazy(addr_space_size: usize) -> Self {
+        let page_size = page_size();
+        let addr_space_size = ceil_align(addr_space_size, page_size);
+
+        Arena {
+            base_addr: Cell::new(ptr::null_mut()),
+            end_addr: Cell::new(ptr::null_mut()),
+            addr_space_size,
+            page_size,
+            uncommitted_addr: Cell::new(ptr::null_mut()),
+            bump_addr: Cell::new(ptr::null_mut()),
+            last_alloc: Cell::new((ptr::null_mut(), 0)),
+            growable: false,
+            peak_used: Cell::new(0),
+            #[cfg(debug_assertions)]
+            allocating: Cell::new(false),
+        }
+    }
 
-            Arena {
-                base_addr,
-                end_addr,
-                page_size,
-                uncommitted_addr,
-                bump_addr,
-            }
+    /// Creates an arena with a small address space that is enlarged with
+    /// `mremap` whenever an allocation doesn't fit in it anymore.
+    ///
+    /// Remapping only works on mappings with a single protection, so the
+    /// entire address space of a growable arena is committed up front; the
+    /// kernel still only backs the pages once they are touched.
+    ///
+    /// # Safety
+    ///
+    /// Growing may move the whole arena to a different address, which makes
+    /// every reference previously handed out by the arena dangle. References
+    /// returned by [`Arena::alloc`], [`Arena::alloc_slice`], [`Arena::boxed`]
+    /// and the like must not be used after a later allocation; allocations
+    /// have to be accessed through offsets from the arena's base instead.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn growable(initial_size: usize) -> Self {
+        let mut arena = Self::lazy(initial_size.max(1));
+        arena.growable = true;
+        arena
+    }
+
+    unsafe fn reserve(&self) {
+        let base_addr = vm_reserve(self.addr_space_size);
+
+        self.base_addr.set(base_addr);
+        self.end_addr.set(base_addr.byte_add(self.addr_space_size));
+        self.uncommitted_addr.set(base_addr);
+        self.bump_addr.set(base_addr);
+
+        if self.growable {
+            vm_commit(base_addr, self.addr_space_size);
+            self.uncommitted_addr.set(self.end_addr.get());
         }
     }
 
+    /// Remaps the address space so at least `needed` bytes fit in it, moving
+    /// the arena if the kernel has to.
+    #[cfg(target_os = "linux")]
+    unsafe fn grow(&self, needed: usize) {
+        let base_addr = self.base_addr.get();
+        let old_size = self.end_addr.get().offset_from(base_addr) as usize;
+        let new_size = ceil_align(needed.max(old_size * 2), self.page_size);
+
+        let new_base_addr = vm_grow(base_addr, old_size, new_size);
+        let rebase = |addr: *mut u8| new_base_addr.byte_offset(addr.offset_from(base_addr));
+
+        self.bump_addr.set(rebase(self.bump_addr.get()));
+        let (last_addr, last_size) = self.last_alloc.get();
+        if !last_addr.is_null() {
+            self.last_alloc.set((rebase(last_addr), last_size));
+        }
+        self.base_addr.set(new_base_addr);
+        self.end_addr.set(new_base_addr.byte_add(new_size));
+        self.uncommitted_addr.set(self.end_addr.get());
+    }
+
+    /// Whether the arena's address space has been reserved yet, which is only
+    /// not the case for [`Arena::lazy`] arenas that have not allocated.
+    #[inline]
+    pub fn is_reserved(&self) -> bool {
+        !self.base_addr.get().is_null()
+    }
+
+    /// Size of the OS's memory pages.
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Number of bytes committed at once when allocations run out of
+    /// committed memory.
     #[inline]
-    fn alloc_granularity(&self) -> usize {
-        unsafe { self.page_size * PAGES_PER_COMMIT }
+    pub fn alloc_granularity_bytes(&self) -> usize {
+        self.page_size * PAGES_PER_COMMIT
+    }
+
+    /// Allocates uninitialized memory for the given layout.
+    #[inline]
+    pub fn alloc_layout(&self, layout: Layout) -> *mut u8 {
+        assert!(layout.align().is_power_of_two());
+        unsafe { self.alloc_region(layout.size(), layout.align()) }
     }
 
     #[inline]
     #[allow(clippy::mut_from_ref)]
     pub fn alloc<T>(&self, value: T) -> &mut T {
         unsafe {
-            let ptr = self.alloc_region(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+            let ptr = self.alloc_layout(Layout::new::<T>()) as *mut T;
             ptr.write(value);
             &mut *ptr
         }
@@ -262,17 +399,116 @@ impl Arena {
         }
     }
 
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_default<T: Default>(&self) -> &mut T {
+        self.alloc(T::default())
+    }
+
+    /// Moves a fixed-size array into the arena. Unlike with
+    /// [`Arena::alloc_slice`], every element is initialized.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_array<T, const N: usize>(&self, arr: [T; N]) -> &mut [T; N] {
+        self.alloc(arr)
+    }
+
+    /// Allocates a slice of `len` elements, each initialized to `f(index)`.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_with<T>(&self, len: usize, mut f: impl FnMut(usize) -> T) -> &mut [T] {
+        unsafe {
+            let ptr = self.alloc_region(len * mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+            for idx in 0..len {
+                ptr.add(idx).write(f(idx));
+            }
+            std::slice::from_raw_parts_mut(ptr, len)
+        }
+    }
+
+    /// Copies `s` into the arena as a null-terminated C string.
+    ///
+    /// Panics if `s` contains a null byte, since C would see the string end
+    /// there.
+    pub fn alloc_cstr(&self, s: &str) -> *const c_char {
+        assert!(
+            !s.as_bytes().contains(&0),
+            "C strings cannot contain null bytes"
+        );
+
+        let bytes = self.alloc_slice_with(s.len() + 1, |idx| {
+            s.as_bytes().get(idx).copied().unwrap_or(0)
+        });
+        bytes.as_ptr() as *const c_char
+    }
+
+    /// Moves `value` into the arena. Unlike with [`Arena::alloc`], it is
+    /// dropped when the returned box is, although its memory stays allocated.
+    pub fn boxed<T>(&self, value: T) -> ArenaBox<'_, T> {
+        ArenaBox {
+            value: self.alloc(value),
+        }
+    }
+
+    /// Extends `slice`, which must be the most recent allocation, by `extra`
+    /// elements in place and returns the whole slice.
+    ///
+    /// Like with [`Arena::alloc_slice`], the new elements are uninitialized.
+    #[allow(clippy::mut_from_ref)]
+    pub fn grow_last_slice<'a, T>(&'a self, slice: &'a mut [T], extra: usize) -> &'a mut [T] {
+        let elem_size = mem::size_of::<T>();
+        assert!(elem_size != 0, "Cannot grow a slice of zero-sized types");
+
+        let size = mem::size_of_val(slice);
+        assert!(
+            self.last_alloc.get() == (slice.as_mut_ptr() as *mut u8, size),
+            "Can only grow the most recent allocation"
+        );
+
+        unsafe {
+            // the last allocation ends at the bump address, so the unaligned
+            // region we allocate here directly follows it. Growable arenas
+            // may move while allocating it, so find the slice from there.
+            let extra_size = extra * elem_size;
+            let addr = self.alloc_region(extra_size, 1).byte_sub(size);
+            self.last_alloc.set((addr, size + extra_size));
+
+            std::slice::from_raw_parts_mut(addr as *mut T, slice.len() + extra)
+        }
+    }
+
     unsafe fn alloc_region(&self, size: usize, align: usize) -> *mut u8 {
-        let addr = ceil_align_ptr(self.bump_addr.get(), align);
-        let next_bump_addr = addr.byte_add(size);
+        // nothing in here calls back into user code today, but an allocation
+        // from the same arena in the middle of this would lose one of the two
+        #[cfg(debug_assertions)]
+        let _guard = AllocGuard::enter(&self.allocating);
+
+        if !self.is_reserved() {
+            self.reserve();
+        }
+
+        let mut addr = ceil_align_ptr(self.bump_addr.get(), align);
+        let mut next_bump_addr = addr.byte_add(size);
+
+        #[cfg(target_os = "linux")]
+        if self.growable && next_bump_addr > self.end_addr.get() {
+            self.grow(next_bump_addr.offset_from(self.base_addr.get()) as usize);
+            addr = ceil_align_ptr(self.bump_addr.get(), align);
+            next_bump_addr = addr.byte_add(size);
+        }
+
+        debug_assert!(
+            addr >= self.base_addr.get() && addr <= self.end_addr.get(),
+            "Aligned allocation address is outside the arena"
+        );
 
         // if next_bump_addr > self.end_addr {
         //     panic!("Arena is out of memory");
         // }
 
-        // commit pages we don't have yet
-        if next_bump_addr >= self.uncommitted_addr.get() {
-            let alloc_granularity = self.alloc_granularity();
+        // commit pages we don't have yet. Growable arenas are committed up
+        // front, and past their end is someone else's memory.
+        if !self.growable && next_bump_addr >= self.uncommitted_addr.get() {
+            let alloc_granularity = self.alloc_granularity_bytes();
             let uncommit_end_addr = ceil_align_ptr(next_bump_addr, alloc_granularity);
             let commit_size = uncommit_end_addr.offset_from(self.uncommitted_addr.get()) as usize;
             vm_commit(self.uncommitted_addr.get(), commit_size);
@@ -280,27 +516,142 @@ impl Arena {
         }
 
         self.bump_addr.set(next_bump_addr);
+        self.last_alloc.set((addr, size));
+
+        let used = next_bump_addr.offset_from(self.base_addr.get()) as usize;
+        self.peak_used.set(self.peak_used.get().max(used));
 
         addr
     }
 
+    /// Commits exactly `n` more pages, independent of how many pages
+    /// allocations usually commit at once.
+    pub fn commit_pages(&self, n: usize) {
+        unsafe {
+            if !self.is_reserved() {
+                self.reserve();
+            }
+
+            let commit_size = n * self.page_size;
+            let uncommitted_addr = self.uncommitted_addr.get();
+            assert!(
+                commit_size <= self.end_addr.get().offset_from(uncommitted_addr) as usize,
+                "Cannot commit {n} pages past the end of the arena"
+            );
+
+            vm_commit(uncommitted_addr, commit_size);
+            self.uncommitted_addr
+                .set(uncommitted_addr.byte_add(commit_size));
+        }
+    }
+
+    /// Number of pages currently committed.
+    pub fn committed_pages(&self) -> usize {
+        if !self.is_reserved() {
+            return 0;
+        }
+
+        unsafe {
+            self.uncommitted_addr
+                .get()
+                .offset_from(self.base_addr.get()) as usize
+                / self.page_size
+        }
+    }
+
+    /// Most bytes that were allocated at once over the arena's lifetime,
+    /// including alignment padding. Unaffected by [`Arena::reset`] and
+    /// [`Arena::free_all`].
+    #[inline]
+    pub fn peak_used(&self) -> usize {
+        self.peak_used.get()
+    }
+
+    /// Frees every allocation but keeps the pages committed, so they can be
+    /// reused without committing them again.
+    pub fn reset(&mut self) {
+        if !self.is_reserved() {
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        self.poison();
+
+        self.bump_addr.set(self.base_addr.get());
+        self.last_alloc.set((ptr::null_mut(), 0));
+    }
+
+    /// Frees every allocation and uncommits the pages they were in.
     pub fn free_all(&mut self) {
+        if !self.is_reserved() || self.growable {
+            self.reset();
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        self.poison();
+
+        unsafe {
+            let uncommit_size = self
+                .uncommitted_addr
+                .get()
+                .offset_from(self.base_addr.get()) as usize;
+            vm_uncommit(self.base_addr.get(), uncommit_size);
+        }
+
+        self.uncommitted_addr.set(self.base_addr.get());
+        self.bump_addr.set(self.base_addr.get());
+        self.last_alloc.set((ptr::null_mut(), 0));
+    }
+
+    /// Uncommits the pages past the current allocations, rounded up to the
+    /// allocation granularity. Useful after a [`Arena::reset`] that followed
+    /// a large peak, without giving up the live allocations like
+    /// [`Arena::free_all`] does.
+    ///
+    /// Does nothing on growable arenas, since uncommitting would split their
+    /// mapping, which can then no longer grow.
+    pub fn shrink_to_fit(&mut self) {
+        if !self.is_reserved() || self.growable {
+            return;
+        }
+
         unsafe {
-            let uncommitted_addr = ceil_align_ptr(self.bump_addr.get(), self.alloc_granularity());
-            let uncommit_size = uncommitted_addr.offset_from(self.base_addr) as usize;
-            vm_uncommit(self.base_addr, uncommit_size);
+            let keep_end = ceil_align_ptr(self.bump_addr.get(), self.alloc_granularity_bytes());
+            let uncommitted_addr = self.uncommitted_addr.get();
+            if keep_end >= uncommitted_addr {
+                return;
+            }
+
+            vm_uncommit(keep_end, uncommitted_addr.offset_from(keep_end) as usize);
+            self.uncommitted_addr.set(keep_end);
         }
+    }
 
-        self.bump_addr.set(self.base_addr);
+    /// Overwrites everything allocated so far with [`POISON`], so references
+    /// that outlive a reset read obviously wrong values instead of stale ones.
+    #[cfg(debug_assertions)]
+    fn poison(&self) {
+        unsafe {
+            let base_addr = self.base_addr.get();
+            let used = self.bump_addr.get().offset_from(base_addr) as usize;
+            ptr::write_bytes(base_addr, POISON, used);
+        }
     }
 }
 
 impl Drop for Arena {
     fn drop(&mut self) {
+        if !self.is_reserved() {
+            return;
+        }
+
         unsafe {
+            // growable arenas may have a larger address space than requested
+            let base_addr = self.base_addr.get();
             vm_release(
-                self.base_addr,
-                self.end_addr.offset_from(self.base_addr) as usize,
+                base_addr,
+                self.end_addr.get().offset_from(base_addr) as usize,
             );
         }
     }
@@ -312,9 +663,70 @@ unsafe fn ceil_align_ptr<T>(ptr: *mut T, to: usize) -> *mut T {
 }
 
 /// Ceil-aligns the value. Assumes a power of 2.
+///
+/// Saturates at `usize::MAX` instead of wrapping to 0 when there is no
+/// aligned value left above `value`.
 #[inline]
 fn ceil_align(value: usize, to: usize) -> usize {
-    (value as isize + (-(value as isize) & (to as isize - 1))) as usize
+    match value.checked_add(to - 1) {
+        Some(value) => value & !(to - 1),
+        None => usize::MAX,
+    }
+}
+
+// box
+
+/// Owns a value allocated by [`Arena::boxed`] and drops it when dropped.
+pub struct ArenaBox<'a, T> {
+    value: &'a mut T,
+}
+
+impl<T> Deref for ArenaBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> DerefMut for ArenaBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<T: Debug> fmt::Debug for ArenaBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T> Drop for ArenaBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.value) }
+    }
+}
+
+/// Marks an arena as being inside [`Arena::alloc_region`] until dropped.
+#[cfg(debug_assertions)]
+struct AllocGuard<'a>(&'a Cell<bool>);
+
+#[cfg(debug_assertions)]
+impl<'a> AllocGuard<'a> {
+    fn enter(allocating: &'a Cell<bool>) -> Self {
+        assert!(
+            !allocating.replace(true),
+            "Re-entrant allocation from the same arena"
+        );
+        Self(allocating)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for AllocGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
 }
 
 // vector
@@ -342,7 +754,7 @@ impl<T> ArenaVec<T> {
             None
         } else {
             unsafe {
-                let ptr = self.arena.base_addr.byte_add(idx * mem::size_of::<T>());
+                let ptr = self.arena.base_addr.get().byte_add(Self::byte_offset(idx));
                 Some(&*(ptr as *const T))
             }
         }
@@ -353,18 +765,18 @@ impl<T> ArenaVec<T> {
             None
         } else {
             unsafe {
-                let ptr = self.arena.base_addr.byte_add(idx * mem::size_of::<T>());
+                let ptr = self.arena.base_addr.get().byte_add(Self::byte_offset(idx));
                 Some(&mut *(ptr as *mut T))
             }
         }
     }
 
     pub fn as_slice(&self) -> &[T] {
-        unsafe { &*slice::from_raw_parts_mut(self.arena.base_addr as _, self.len()) }
+        unsafe { &*slice::from_raw_parts_mut(self.arena.base_addr.get() as _, self.len()) }
     }
 
     pub fn as_mut_slice(&mut self) -> &mut [T] {
-        unsafe { &mut *slice::from_raw_parts_mut(self.arena.base_addr as _, self.len()) }
+        unsafe { &mut *slice::from_raw_parts_mut(self.arena.base_addr.get() as _, self.len()) }
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &'_ T> {
@@ -376,7 +788,8 @@ impl<T> ArenaVec<T> {
     }
 
     pub fn len(&self) -> usize {
-        let bytes = unsafe { (self.arena.bump_addr.get()).byte_offset_from(self.arena.base_addr) };
+        let bytes =
+            unsafe { (self.arena.bump_addr.get()).byte_offset_from(self.arena.base_addr.get()) };
         bytes as usize / mem::size_of::<T>()
     }
 
@@ -384,9 +797,65 @@ impl<T> ArenaVec<T> {
         self.len() == 0
     }
 
+    /// Removes the last element and returns it. Its memory stays committed for
+    /// the next element.
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+
+        unsafe {
+            let ptr = self
+                .arena
+                .base_addr
+                .get()
+                .byte_add(Self::byte_offset(len - 1));
+            self.arena.bump_addr.set(ptr);
+            self.arena.last_alloc.set((ptr::null_mut(), 0));
+            Some((ptr as *mut T).read())
+        }
+    }
+
+    /// Removes the element at `idx` and returns it, moving the last element
+    /// into its place. Doesn't keep the order, but doesn't shift anything
+    /// either.
+    pub fn swap_remove(&mut self, idx: usize) -> T {
+        let len = self.len();
+        if idx >= len {
+            panic!("Index out of bounds: {idx} >= {len}");
+        }
+
+        self.as_mut_slice().swap(idx, len - 1);
+        self.pop().unwrap()
+    }
+
+    /// Removes the element at `idx` and returns it, shifting the elements after
+    /// it down by one.
+    pub fn remove(&mut self, idx: usize) -> T {
+        let len = self.len();
+        if idx >= len {
+            panic!("Index out of bounds: {idx} >= {len}");
+        }
+
+        self.as_mut_slice()[idx..].rotate_left(1);
+        self.pop().unwrap()
+    }
+
+    /// Removes every element, without dropping them, and uncommits the memory
+    /// they were in. Elements added afterwards start at index 0 again.
     pub fn clear(&mut self) {
         self.arena.free_all();
     }
+
+    /// Offset of the element at `idx` from the start of the vector. Panics
+    /// instead of wrapping around to a bogus offset if it doesn't fit in a
+    /// `usize`.
+    #[inline]
+    fn byte_offset(idx: usize) -> usize {
+        idx.checked_mul(mem::size_of::<T>())
+            .unwrap_or_else(|| panic!("ArenaVec offset of index {idx} overflows usize"))
+    }
 }
 
 impl<T> Index<usize> for ArenaVec<T> {
@@ -409,19 +878,233 @@ impl<T> IndexMut<usize> for ArenaVec<T> {
             panic!("Index out of bounds: {index} >= {len}");
         } else {
             unsafe {
-                let ptr = self.arena.base_addr.byte_add(index * mem::size_of::<T>());
+                let ptr = self
+                    .arena
+                    .base_addr
+                    .get()
+                    .byte_add(Self::byte_offset(index));
                 &mut *(ptr as *mut T)
             }
         }
     }
 }
 
+// The arena's pages are owned by the vector and only reachable through it, so
+// moving it to another thread is as sound as moving its elements. It stays
+// !Sync since the arena's bump pointer is a Cell.
+unsafe impl<T: Send> Send for ArenaVec<T> {}
+
 impl<T: Debug> fmt::Debug for ArenaVec<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
     }
 }
 
+// pool
+
+/// Refers to a value in a [`PoolArena`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(usize);
+
+/// Free slots store the index of the next free slot in place of a value.
+union Slot<T> {
+    value: ManuallyDrop<T>,
+    next_free: Option<usize>,
+}
+
+/// Allocates values of a single type from an arena, reusing the slots of
+/// freed values.
+///
+/// Using a handle after its value is freed, or a handle that is out of
+/// bounds for this pool, panics. Like with [`ArenaVec`], values that are
+/// never freed are never dropped.
+pub struct PoolArena<T> {
+    arena: Arena,
+    /// Number of slots ever allocated
+    slots: usize,
+    free_head: Option<usize>,
+    /// Whether each slot holds a value
+    live: Vec<bool>,
+    _data: PhantomData<T>,
+}
+
+impl<T> PoolArena<T> {
+    pub fn new(addr_space_size: usize) -> Self {
+        Self {
+            arena: Arena::lazy(addr_space_size),
+            slots: 0,
+            free_head: None,
+            live: Vec::new(),
+            _data: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn slot(&self, idx: usize) -> *mut Slot<T> {
+        unsafe { (self.arena.base_addr.get() as *mut Slot<T>).add(idx) }
+    }
+
+    /// Also bounds-checks the handle, since `live` has an entry per slot.
+    #[inline]
+    fn assert_live(&self, handle: Handle, action: &str) {
+        assert!(
+            self.live.get(handle.0) == Some(&true),
+            "Cannot {action} a freed pool slot"
+        );
+    }
+
+    pub fn alloc(&mut self, value: T) -> Handle {
+        let value = ManuallyDrop::new(value);
+
+        let idx = match self.free_head {
+            Some(idx) => unsafe {
+                let slot = self.slot(idx);
+                self.free_head = (*slot).next_free;
+                slot.write(Slot { value });
+                idx
+            },
+            None => {
+                // slots are the only allocations, so they are contiguous
+                self.arena.alloc(Slot { value });
+                self.slots += 1;
+                self.live.push(false);
+                self.slots - 1
+            }
+        };
+
+        self.live[idx] = true;
+        Handle(idx)
+    }
+
+    /// Frees the value's slot for reuse and returns the value.
+    pub fn free(&mut self, handle: Handle) -> T {
+        self.assert_live(handle, "free");
+        self.live[handle.0] = false;
+
+        unsafe {
+            let slot = self.slot(handle.0);
+            let value = ManuallyDrop::take(&mut (*slot).value);
+            slot.write(Slot {
+                next_free: self.free_head,
+            });
+            self.free_head = Some(handle.0);
+            value
+        }
+    }
+
+    pub fn get(&self, handle: Handle) -> &T {
+        self.assert_live(handle, "access");
+        unsafe { &(*self.slot(handle.0)).value }
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> &mut T {
+        self.assert_live(handle, "access");
+        unsafe { &mut (*self.slot(handle.0)).value }
+    }
+}
+
+// map
+
+/// An open-addressing hash map with linear probing whose tables live in an
+/// arena.
+///
+/// Growing allocates a table twice as large and leaves the old one behind in
+/// the arena. Like with [`ArenaVec`], entries are never dropped.
+pub struct ArenaMap<K, V> {
+    arena: Arena,
+    slots: *mut Option<(K, V)>,
+    /// Always a power of two, so the hash can be masked instead of divided
+    capacity: usize,
+    len: usize,
+}
+
+impl<K: Hash + Eq, V> ArenaMap<K, V> {
+    /// Creates a map that holds `capacity` entries before having to grow.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let arena = Arena::lazy(64 * GIB);
+        let capacity = Self::table_size(capacity);
+        let slots = arena.alloc_slice_with(capacity, |_| None).as_mut_ptr();
+
+        Self {
+            arena,
+            slots,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Smallest table that stays at most 3/4 full with `entries` entries.
+    fn table_size(entries: usize) -> usize {
+        (entries * 4).div_ceil(3).max(1).next_power_of_two()
+    }
+
+    fn hash(key: &K) -> u64 {
+        BuildHasherDefault::<DefaultHasher>::default().hash_one(key)
+    }
+
+    /// Index of the slot holding `key`, or of the empty slot it would go in.
+    fn probe(slots: *mut Option<(K, V)>, capacity: usize, key: &K) -> usize {
+        let mut idx = Self::hash(key) as usize & (capacity - 1);
+        loop {
+            match unsafe { &*slots.add(idx) } {
+                Some((existing, _)) if existing != key => idx = (idx + 1) & (capacity - 1),
+                _ => return idx,
+            }
+        }
+    }
+
+    /// Inserts a value, returning the previous value of the key if there was
+    /// one.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if Self::table_size(self.len + 1) > self.capacity {
+            self.grow();
+        }
+
+        let idx = Self::probe(self.slots, self.capacity, &key);
+        let slot = unsafe { &mut *self.slots.add(idx) };
+        let old = slot.replace((key, value)).map(|(_, value)| value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let idx = Self::probe(self.slots, self.capacity, key);
+        unsafe { &*self.slots.add(idx) }
+            .as_ref()
+            .map(|(_, value)| value)
+    }
+
+    fn grow(&mut self) {
+        let capacity = self.capacity * 2;
+        let slots = self.arena.alloc_slice_with(capacity, |_| None).as_mut_ptr();
+
+        for idx in 0..self.capacity {
+            if let Some((key, value)) = unsafe { (*self.slots.add(idx)).take() } {
+                let new_idx = Self::probe(slots, capacity, &key);
+                unsafe { *slots.add(new_idx) = Some((key, value)) };
+            }
+        }
+
+        self.slots = slots;
+        self.capacity = capacity;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of entries the map can hold before it has to grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity / 4 * 3
+    }
+}
+
 #[cfg(test)]
 mod tests_ceil_align {
     use crate::arena::ceil_align;
@@ -454,4 +1137,633 @@ mod tests_ceil_align {
         assert_eq!(ceil_align(18, 16), 32);
         assert_eq!(ceil_align(19, 16), 32);
     }
+
+    #[test]
+    fn no_wrap() {
+        assert_eq!(ceil_align(usize::MAX - 15, 16), usize::MAX - 15);
+        assert_eq!(ceil_align(usize::MAX - 14, 16), usize::MAX);
+        assert_eq!(ceil_align(usize::MAX, 8), usize::MAX);
+        assert_eq!(ceil_align(usize::MAX, 1), usize::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests_send {
+    use std::thread;
+
+    use crate::arena::{ArenaVec, MIB};
+
+    #[test]
+    fn move_to_thread() {
+        let vec = ArenaVec::<u32>::new(MIB);
+        for i in 0..100 {
+            vec.add(i);
+        }
+
+        let sum = thread::spawn(move || vec.iter().sum::<u32>())
+            .join()
+            .unwrap();
+        assert_eq!(sum, (0..100).sum());
+    }
+}
+
+#[cfg(test)]
+mod tests_grow_last_slice {
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn grow_twice() {
+        let arena = Arena::new(MIB);
+
+        let slice = arena.alloc_slice::<u32>(2);
+        slice.copy_from_slice(&[1, 2]);
+
+        let slice = arena.grow_last_slice(slice, 3);
+        assert_eq!(slice.len(), 5);
+        slice[2..].copy_from_slice(&[3, 4, 5]);
+
+        let slice = arena.grow_last_slice(slice, 1);
+        assert_eq!(slice.len(), 6);
+        slice[5] = 6;
+
+        assert_eq!(slice, &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "most recent allocation")]
+    fn not_last() {
+        let arena = Arena::new(MIB);
+        let slice = arena.alloc_slice::<u64>(2);
+        arena.alloc(1u8);
+        arena.grow_last_slice(slice, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "most recent allocation")]
+    fn older_slice_of_same_size() {
+        let arena = Arena::new(MIB);
+        let first = arena.alloc_slice::<u64>(2);
+        arena.alloc_slice::<u64>(2);
+        arena.grow_last_slice(first, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests_lazy {
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn reserve_on_first_alloc() {
+        let arena = Arena::lazy(MIB);
+        assert!(!arena.is_reserved());
+
+        let value = arena.alloc(42u64);
+        assert!(arena.is_reserved());
+        assert_eq!(*value, 42);
+
+        let slice = arena.alloc_slice::<u8>(3);
+        slice.copy_from_slice(b"abc");
+        assert_eq!(slice, b"abc");
+    }
+
+    #[test]
+    fn never_reserved() {
+        let mut arena = Arena::lazy(MIB);
+        arena.free_all();
+        assert!(!arena.is_reserved());
+    }
+}
+
+#[cfg(test)]
+mod tests_alloc_with {
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn alloc_default() {
+        let arena = Arena::new(MIB);
+        let value = arena.alloc_default::<(u32, bool)>();
+        assert_eq!(*value, (0, false));
+    }
+
+    #[test]
+    fn squares() {
+        let arena = Arena::new(MIB);
+        let squares = arena.alloc_slice_with(5, |i| i * i);
+        assert_eq!(squares, &[0, 1, 4, 9, 16]);
+
+        let empty = arena.alloc_slice_with(0, |i| i);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn array() {
+        let arena = Arena::new(MIB);
+        let arr = arena.alloc_array([1u32, 2, 3, 4]);
+        arr[3] = 5;
+        assert_eq!(*arr, [1, 2, 3, 5]);
+        assert_eq!(arr.as_ptr() as usize % std::mem::align_of::<u32>(), 0);
+
+        let empty: &mut [u32; 0] = arena.alloc_array([]);
+        assert!(empty.is_empty());
+
+        // later allocations don't overlap
+        let next = arena.alloc(6u32);
+        assert_eq!(*next, 6);
+        assert_eq!(*arr, [1, 2, 3, 5]);
+    }
+}
+
+#[cfg(test)]
+mod tests_commit_pages {
+    use crate::arena::{page_size, Arena, MIB};
+
+    #[test]
+    fn exact_pages() {
+        let arena = Arena::lazy(MIB);
+        assert_eq!(arena.committed_pages(), 0);
+
+        arena.commit_pages(3);
+        assert_eq!(arena.committed_pages(), 3);
+
+        // allocations fit in the committed pages without committing more
+        arena.alloc_slice::<u8>(page_size());
+        assert_eq!(arena.committed_pages(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn past_end() {
+        let arena = Arena::new(MIB);
+        arena.commit_pages(MIB / page_size() + 1);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut arena = Arena::new(64 * MIB);
+        arena.alloc_slice::<u8>(16 * MIB).fill(1);
+        let peak = arena.committed_pages();
+
+        arena.reset();
+        arena.alloc(5u64);
+        arena.shrink_to_fit();
+        let granularity_pages = arena.alloc_granularity_bytes() / page_size();
+        assert_eq!(arena.committed_pages(), granularity_pages);
+        assert!(arena.committed_pages() < peak);
+
+        // freed pages are committed again when needed
+        arena.alloc_slice::<u8>(MIB).fill(2);
+        assert!(arena.committed_pages() > granularity_pages);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests_growable {
+    use crate::arena::{page_size, Arena};
+
+    #[test]
+    fn grows_past_initial_size() {
+        let arena = unsafe { Arena::growable(page_size()) };
+
+        let first = arena.alloc_slice_with(page_size(), |idx| idx as u8);
+        assert_eq!(first[200], 200);
+
+        // doesn't fit anymore, so the arena has to be remapped
+        let second = arena.alloc_slice_with(4 * page_size(), |idx| (idx % 7) as u8);
+        assert_eq!(
+            second[4 * page_size() - 1],
+            ((4 * page_size() - 1) % 7) as u8
+        );
+
+        // the first allocation moved along with the arena
+        let base = arena.base_addr.get();
+        assert_eq!(unsafe { *base.add(200) }, 200);
+        assert!(arena.end_addr.get() >= unsafe { base.add(5 * page_size()) });
+
+        let value = arena.alloc(42u64);
+        assert_eq!(*value, 42);
+    }
+
+    #[test]
+    fn exact_fit() {
+        let arena = unsafe { Arena::growable(page_size()) };
+        arena.alloc_slice_with(page_size(), |idx| idx as u8);
+
+        // filling the initial size neither grows the arena nor commits past it
+        let base = arena.base_addr.get();
+        assert_eq!(arena.end_addr.get(), unsafe { base.add(page_size()) });
+        assert_eq!(arena.uncommitted_addr.get(), arena.end_addr.get());
+        assert_eq!(arena.bump_addr.get(), arena.end_addr.get());
+    }
+}
+
+#[cfg(test)]
+mod tests_alloc_layout {
+    use std::alloc::Layout;
+
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn aligned_and_disjoint() {
+        let arena = Arena::new(MIB);
+        arena.alloc(1u8);
+
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let first = arena.alloc_layout(layout);
+        let second = arena.alloc_layout(layout);
+
+        assert!(first.cast::<u64>().is_aligned());
+        assert!(second.cast::<u64>().is_aligned());
+        assert!(second as usize >= first as usize + 24);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_map {
+    use crate::arena::ArenaMap;
+
+    /// Hashes to the same value for every key, so every insert collides.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Colliding(u32);
+
+    impl std::hash::Hash for Colliding {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            0.hash(state);
+        }
+    }
+
+    #[test]
+    fn insert_get() {
+        let mut map = ArenaMap::with_capacity(8);
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            assert_eq!(map.insert(key, value), None);
+        }
+
+        assert_eq!(map.get(&"a"), Some(&1));
+        assert_eq!(map.get(&"c"), Some(&3));
+        assert_eq!(map.get(&"d"), None);
+
+        assert_eq!(map.insert("b", 20), Some(2));
+        assert_eq!(map.get(&"b"), Some(&20));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn collisions() {
+        let mut map = ArenaMap::with_capacity(4);
+        for i in 0..4 {
+            map.insert(Colliding(i), i * 10);
+        }
+
+        for i in 0..4 {
+            assert_eq!(map.get(&Colliding(i)), Some(&(i * 10)));
+        }
+        assert_eq!(map.get(&Colliding(4)), None);
+    }
+
+    #[test]
+    fn grow() {
+        let mut map = ArenaMap::with_capacity(2);
+        let capacity = map.capacity();
+
+        for i in 0..1000 {
+            map.insert(i, i.to_string());
+        }
+
+        assert!(map.capacity() > capacity);
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i.to_string()));
+        }
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests_poison {
+    use std::alloc::Layout;
+
+    use crate::arena::{Arena, MIB, POISON};
+
+    fn reused_bytes(arena: &Arena) -> [u8; 8] {
+        let ptr = arena.alloc_layout(Layout::new::<[u8; 8]>());
+        unsafe { ptr.cast::<[u8; 8]>().read() }
+    }
+
+    #[test]
+    fn reset() {
+        let mut arena = Arena::new(MIB);
+        arena.alloc(u64::MAX);
+        arena.reset();
+
+        assert_eq!(reused_bytes(&arena), [POISON; 8]);
+    }
+
+    // decommitted memory is zeroed on Windows, but keeps its contents on unix
+    #[cfg(unix)]
+    #[test]
+    fn free_all() {
+        let mut arena = Arena::new(MIB);
+        arena.alloc(u64::MAX);
+        arena.free_all();
+
+        assert_eq!(reused_bytes(&arena), [POISON; 8]);
+    }
+}
+
+#[cfg(test)]
+mod tests_granularity {
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn multiple_of_page_size() {
+        let arena = Arena::lazy(MIB);
+        assert!(arena.page_size().is_power_of_two());
+        assert!(arena
+            .alloc_granularity_bytes()
+            .is_multiple_of(arena.page_size()));
+        assert!(arena.alloc_granularity_bytes() >= arena.page_size());
+    }
+}
+
+#[cfg(test)]
+mod tests_peak_used {
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn high_water_mark() {
+        let mut arena = Arena::lazy(MIB);
+        assert_eq!(arena.peak_used(), 0);
+
+        arena.alloc_slice::<u8>(1000);
+        arena.alloc_slice::<u8>(24);
+        assert_eq!(arena.peak_used(), 1024);
+
+        arena.reset();
+        arena.alloc_slice::<u8>(100);
+        assert_eq!(arena.peak_used(), 1024);
+
+        arena.free_all();
+        arena.alloc_slice::<u8>(2000);
+        assert_eq!(arena.peak_used(), 2000);
+    }
+}
+
+#[cfg(test)]
+mod tests_alloc_cstr {
+    use std::ffi::CStr;
+
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn round_trip() {
+        let arena = Arena::new(MIB);
+        let empty = arena.alloc_cstr("");
+        let ptr = arena.alloc_cstr("hello ඞ");
+
+        assert_eq!(unsafe { CStr::from_ptr(ptr) }.to_str(), Ok("hello ඞ"));
+        assert_eq!(unsafe { CStr::from_ptr(empty) }.to_bytes(), b"");
+    }
+
+    #[test]
+    #[should_panic(expected = "null bytes")]
+    fn interior_null() {
+        Arena::new(MIB).alloc_cstr("a\0b");
+    }
+}
+
+#[cfg(test)]
+mod tests_pool_arena {
+    use crate::arena::{PoolArena, MIB};
+
+    #[test]
+    fn reuse() {
+        let mut pool = PoolArena::new(MIB);
+        let a = pool.alloc(String::from("a"));
+        let b = pool.alloc(String::from("b"));
+        let c = pool.alloc(String::from("c"));
+
+        assert_eq!(pool.free(b), "b");
+        assert_eq!(pool.free(a), "a");
+
+        // freed slots are reused, most recently freed first
+        let d = pool.alloc(String::from("d"));
+        let e = pool.alloc(String::from("e"));
+        let f = pool.alloc(String::from("f"));
+        assert_eq!((d, e), (a, b));
+        assert_ne!(f, c);
+
+        pool.get_mut(d).push('!');
+        assert_eq!(pool.get(d), "d!");
+        assert_eq!(pool.get(c), "c");
+        assert_eq!(pool.get(f), "f");
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot free a freed pool slot")]
+    fn double_free() {
+        let mut pool = PoolArena::new(MIB);
+        let a = pool.alloc(1u64);
+        pool.free(a);
+        pool.free(a);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot access a freed pool slot")]
+    fn use_after_free() {
+        let mut pool = PoolArena::new(MIB);
+        let a = pool.alloc(String::from("a"));
+        pool.free(a);
+        pool.get(a);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot access a freed pool slot")]
+    fn foreign_handle() {
+        let mut big = PoolArena::new(MIB);
+        big.alloc(1u64);
+        let b = big.alloc(2u64);
+        let mut small = PoolArena::new(MIB);
+        small.alloc(3u64);
+        small.get_mut(b);
+    }
+}
+
+#[cfg(test)]
+mod tests_boxed {
+    use std::cell::Cell;
+
+    use crate::arena::{Arena, MIB};
+
+    struct CountDrops<'a>(&'a Cell<usize>);
+
+    impl Drop for CountDrops<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drops_value() {
+        let drops = Cell::new(0);
+        let arena = Arena::new(MIB);
+
+        {
+            let boxed = arena.boxed(CountDrops(&drops));
+            assert_eq!(boxed.0.get(), 0);
+        }
+        assert_eq!(drops.get(), 1);
+
+        // plain allocations are never dropped
+        arena.alloc(CountDrops(&drops));
+        let mut boxed = arena.boxed(vec![1, 2]);
+        boxed.push(3);
+        assert_eq!(*boxed, [1, 2, 3]);
+        drop(boxed);
+        drop(arena);
+        assert_eq!(drops.get(), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_vec_clear {
+    use crate::arena::{page_size, ArenaVec, MIB};
+
+    #[test]
+    fn refill() {
+        let mut vec = ArenaVec::new(64 * MIB);
+        // more than a single commit, so clearing uncommits several pages
+        let count = 64 * page_size() / 8;
+        for value in 0..count as u64 {
+            vec.add(value);
+        }
+
+        vec.clear();
+        assert!(vec.is_empty());
+        assert_eq!(vec.get(0), None);
+        assert_eq!(vec.get_mut(0), None);
+        assert_eq!(vec.as_slice(), []);
+
+        vec.add(u64::MAX);
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec.get(0), Some(&u64::MAX));
+        assert_eq!(vec.get(1), None);
+
+        for value in 1..count as u64 {
+            vec.add(value + 1000);
+        }
+        assert_eq!(vec[count - 1], count as u64 - 1 + 1000);
+        assert!(vec.iter().skip(1).all(|&value| value >= 1000));
+    }
+}
+
+#[cfg(test)]
+mod tests_byte_offset {
+    use crate::arena::ArenaVec;
+
+    #[test]
+    fn in_range() {
+        assert_eq!(ArenaVec::<u64>::byte_offset(3), 24);
+        assert_eq!(ArenaVec::<[u8; 1 << 20]>::byte_offset(2), 2 << 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows usize")]
+    fn overflow() {
+        // wraps to 0 without the check
+        ArenaVec::<[u8; 1 << 20]>::byte_offset(1 << (usize::BITS - 20));
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_vec_remove {
+    use std::rc::Rc;
+
+    use crate::arena::{ArenaVec, MIB};
+
+    fn vec_of(items: &[u32]) -> ArenaVec<u32> {
+        let vec = ArenaVec::new(MIB);
+        for &item in items {
+            vec.add(item);
+        }
+        vec
+    }
+
+    #[test]
+    fn remove_keeps_order() {
+        let mut vec = vec_of(&[1, 2, 3, 4, 5]);
+        assert_eq!(vec.remove(1), 2);
+        assert_eq!(vec.as_slice(), [1, 3, 4, 5]);
+        assert_eq!(vec.remove(3), 5);
+        assert_eq!(vec.as_slice(), [1, 3, 4]);
+
+        // the freed space is reused
+        vec.add(6);
+        assert_eq!(vec.as_slice(), [1, 3, 4, 6]);
+    }
+
+    #[test]
+    fn swap_remove_moves_last() {
+        let mut vec = vec_of(&[1, 2, 3, 4, 5]);
+        assert_eq!(vec.swap_remove(1), 2);
+        assert_eq!(vec.as_slice(), [1, 5, 3, 4]);
+        assert_eq!(vec.swap_remove(3), 4);
+        assert_eq!(vec.as_slice(), [1, 5, 3]);
+
+        assert_eq!(vec.pop(), Some(3));
+        assert_eq!(vec.pop(), Some(5));
+        assert_eq!(vec.pop(), Some(1));
+        assert_eq!(vec.pop(), None);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn drops_once() {
+        let rc = Rc::new(());
+        let mut vec = ArenaVec::new(MIB);
+        for _ in 0..3 {
+            vec.add(rc.clone());
+        }
+
+        drop(vec.remove(0));
+        drop(vec.swap_remove(0));
+        assert_eq!(Rc::strong_count(&rc), 2);
+        assert_eq!(vec.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Index out of bounds: 3 >= 3")]
+    fn remove_out_of_bounds() {
+        vec_of(&[1, 2, 3]).remove(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Index out of bounds: 0 >= 0")]
+    fn swap_remove_out_of_bounds() {
+        vec_of(&[]).swap_remove(0);
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests_reentrancy {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::arena::{Arena, MIB};
+
+    #[test]
+    fn nested_alloc_panics() {
+        let arena = Arena::new(MIB);
+        arena.alloc(1u32);
+
+        // as if `alloc_region` were interrupted by another allocation
+        arena.allocating.set(true);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            arena.alloc(2u32);
+        }));
+        let msg = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(msg, "Re-entrant allocation from the same arena");
+
+        // the guard is released after every allocation
+        arena.allocating.set(false);
+        assert_eq!(*arena.alloc(3u32), 3);
+        assert!(!arena.allocating.get());
+    }
 }
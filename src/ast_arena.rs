@@ -0,0 +1,354 @@
+//! AST storage grouping nodes by kind.
+//!
+//! [`Expr`] trees box every node separately. An [`AstArena`] stores each kind
+//! of node contiguously instead, so passes that only care about one kind,
+//! like all binary operations, walk a single array.
+
+use crate::{
+    arena::{ArenaVec, GIB},
+    lexer::TokenType,
+    parser::{Expr, Item, Stmt},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Atom,
+    Unary,
+    Binary,
+    Index,
+    Field,
+    Call,
+    Array,
+    If,
+    DoWhile,
+    Block,
+}
+
+/// Refers to a node in an [`AstArena`], by its kind and its index among the
+/// nodes of that kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    pub kind: NodeKind,
+    pub idx: u32,
+}
+
+/// Consecutive node ids in [`AstArena::lists`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeList {
+    pub start: u32,
+    pub len: u32,
+}
+
+/// Consecutive statements in [`AstArena::stmts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StmtList {
+    pub start: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomKind {
+    Ident,
+    Num,
+    String,
+    Char,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Atom<'a> {
+    pub kind: AtomKind,
+    pub text: &'a str,
+}
+
+/// `op expr`, or `expr op` for postfix `++` and `--`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unary {
+    pub op: TokenType,
+    pub postfix: bool,
+    pub expr: NodeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binary {
+    pub op: TokenType,
+    pub lhs: NodeId,
+    pub rhs: NodeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Index {
+    pub base: NodeId,
+    pub index: NodeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field<'a> {
+    pub base: NodeId,
+    pub field: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Call {
+    pub callee: NodeId,
+    pub args: NodeList,
+}
+
+/// `if cond then a else b`, or `cond ? a : b` if `ternary` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct If {
+    pub cond: NodeId,
+    pub then: NodeId,
+    pub else_: Option<NodeId>,
+    pub ternary: bool,
+}
+
+/// `do body while cond`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoWhile {
+    pub body: NodeId,
+    pub cond: NodeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StmtNode {
+    /// Index into [`AstArena::items`]
+    Item(u32),
+    Expr(NodeId),
+    Semi(NodeId),
+}
+
+/// Nodes of one or more parsed trees, in arrays per node kind. Children are
+/// always added before their parents.
+#[derive(Debug)]
+pub struct AstArena<'a> {
+    pub atoms: ArenaVec<Atom<'a>>,
+    pub unaries: ArenaVec<Unary>,
+    pub binaries: ArenaVec<Binary>,
+    pub indexes: ArenaVec<Index>,
+    pub fields: ArenaVec<Field<'a>>,
+    pub calls: ArenaVec<Call>,
+    pub arrays: ArenaVec<NodeList>,
+    pub ifs: ArenaVec<If>,
+    pub do_whiles: ArenaVec<DoWhile>,
+    pub blocks: ArenaVec<StmtList>,
+    /// Call arguments and array elements
+    pub lists: ArenaVec<NodeId>,
+    /// Statements of blocks
+    pub stmts: ArenaVec<StmtNode>,
+    /// Items keep their tree form, since they are rare inside expressions
+    pub items: Vec<Item<'a>>,
+}
+
+impl<'a> AstArena<'a> {
+    pub fn new() -> Self {
+        Self {
+            atoms: ArenaVec::new(GIB),
+            unaries: ArenaVec::new(GIB),
+            binaries: ArenaVec::new(GIB),
+            indexes: ArenaVec::new(GIB),
+            fields: ArenaVec::new(GIB),
+            calls: ArenaVec::new(GIB),
+            arrays: ArenaVec::new(GIB),
+            ifs: ArenaVec::new(GIB),
+            do_whiles: ArenaVec::new(GIB),
+            blocks: ArenaVec::new(GIB),
+            lists: ArenaVec::new(GIB),
+            stmts: ArenaVec::new(GIB),
+            items: Vec::new(),
+        }
+    }
+
+    /// Node ids of a call's arguments or an array's elements.
+    pub fn list(&self, list: NodeList) -> &[NodeId] {
+        let start = list.start as usize;
+        &self.lists.as_slice()[start..start + list.len as usize]
+    }
+
+    /// Statements of a block.
+    pub fn block_stmts(&self, list: StmtList) -> &[StmtNode] {
+        let start = list.start as usize;
+        &self.stmts.as_slice()[start..start + list.len as usize]
+    }
+
+    /// Copies parsed statements, like the output of
+    /// [`parse`](crate::parser::parse), into the arena.
+    pub fn add_stmts(&mut self, stmts: &[Stmt<'a>]) -> StmtList {
+        let nodes: Vec<StmtNode> = stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Item(item) => {
+                    self.items.push(item.clone());
+                    StmtNode::Item(self.items.len() as u32 - 1)
+                }
+                Stmt::Expr(expr) => StmtNode::Expr(self.add_expr(expr)),
+                Stmt::Semi(expr) => StmtNode::Semi(self.add_expr(expr)),
+            })
+            .collect();
+
+        // children are added first, so the list itself is contiguous
+        let start = self.stmts.len() as u32;
+        for node in nodes {
+            self.stmts.add(node);
+        }
+        StmtList {
+            start,
+            len: stmts.len() as u32,
+        }
+    }
+
+    /// Copies an expression tree into the arena.
+    pub fn add_expr(&mut self, expr: &Expr<'a>) -> NodeId {
+        match expr {
+            Expr::Ident(text) => self.add_atom(AtomKind::Ident, text),
+            Expr::Num(text) => self.add_atom(AtomKind::Num, text),
+            Expr::String(text) => self.add_atom(AtomKind::String, text),
+            Expr::Char(text) => self.add_atom(AtomKind::Char, text),
+            Expr::Array(items) => {
+                let items = self.add_list(items);
+                push(&self.arrays, NodeKind::Array, items)
+            }
+            Expr::Index { base, index } => {
+                let index = Index {
+                    base: self.add_expr(base),
+                    index: self.add_expr(index),
+                };
+                push(&self.indexes, NodeKind::Index, index)
+            }
+            Expr::Field { base, field } => {
+                let field = Field {
+                    base: self.add_expr(base),
+                    field,
+                };
+                push(&self.fields, NodeKind::Field, field)
+            }
+            Expr::Call { callee, args } => {
+                let call = Call {
+                    callee: self.add_expr(callee),
+                    args: self.add_list(args),
+                };
+                push(&self.calls, NodeKind::Call, call)
+            }
+            Expr::PreIncr(expr) => self.add_unary(TokenType::Incr, false, expr),
+            Expr::PreDecr(expr) => self.add_unary(TokenType::Decr, false, expr),
+            Expr::PostIncr(expr) => self.add_unary(TokenType::Incr, true, expr),
+            Expr::PostDecr(expr) => self.add_unary(TokenType::Decr, true, expr),
+            Expr::Unary { op, expr } => self.add_unary(*op, false, expr),
+            Expr::Binary { op, lhs, rhs } => {
+                let binary = Binary {
+                    op: *op,
+                    lhs: self.add_expr(lhs),
+                    rhs: self.add_expr(rhs),
+                };
+                push(&self.binaries, NodeKind::Binary, binary)
+            }
+            Expr::Block(stmts) => {
+                let stmts = self.add_stmts(stmts);
+                push(&self.blocks, NodeKind::Block, stmts)
+            }
+            Expr::If { cond, then, else_ } => {
+                let if_ = If {
+                    cond: self.add_expr(cond),
+                    then: self.add_expr(then),
+                    else_: else_.as_ref().map(|else_| self.add_expr(else_)),
+                    ternary: false,
+                };
+                push(&self.ifs, NodeKind::If, if_)
+            }
+            Expr::Ternary { cond, then, else_ } => {
+                let if_ = If {
+                    cond: self.add_expr(cond),
+                    then: self.add_expr(then),
+                    else_: Some(self.add_expr(else_)),
+                    ternary: true,
+                };
+                push(&self.ifs, NodeKind::If, if_)
+            }
+            Expr::DoWhile { body, cond } => {
+                let do_while = DoWhile {
+                    body: self.add_expr(body),
+                    cond: self.add_expr(cond),
+                };
+                push(&self.do_whiles, NodeKind::DoWhile, do_while)
+            }
+        }
+    }
+
+    fn add_atom(&mut self, kind: AtomKind, text: &'a str) -> NodeId {
+        push(&self.atoms, NodeKind::Atom, Atom { kind, text })
+    }
+
+    fn add_unary(&mut self, op: TokenType, postfix: bool, expr: &Expr<'a>) -> NodeId {
+        let unary = Unary {
+            op,
+            postfix,
+            expr: self.add_expr(expr),
+        };
+        push(&self.unaries, NodeKind::Unary, unary)
+    }
+
+    fn add_list(&mut self, exprs: &[Expr<'a>]) -> NodeList {
+        let ids: Vec<NodeId> = exprs.iter().map(|expr| self.add_expr(expr)).collect();
+
+        let start = self.lists.len() as u32;
+        for id in ids {
+            self.lists.add(id);
+        }
+        NodeList {
+            start,
+            len: exprs.len() as u32,
+        }
+    }
+}
+
+impl Default for AstArena<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn push<T>(nodes: &ArenaVec<T>, kind: NodeKind, node: T) -> NodeId {
+    let idx = nodes.len() as u32;
+    nodes.add(node);
+    NodeId { kind, idx }
+}
+
+#[cfg(test)]
+mod tests_ast_arena {
+    use crate::{
+        ast_arena::{AstArena, AtomKind, NodeKind, StmtNode},
+        lexer::{lex, TokenType},
+        parser::parse,
+    };
+
+    #[test]
+    fn binaries_contiguous() {
+        let tokens = lex("test", "f(a + b * 2, [c - d]);\ne ** 2\n");
+        let stmts = parse(&tokens).unwrap();
+
+        let mut ast = AstArena::new();
+        let list = ast.add_stmts(&stmts);
+        assert!(matches!(
+            ast.block_stmts(list),
+            [StmtNode::Semi(_), StmtNode::Expr(_)]
+        ));
+
+        // children are added before their parents
+        let ops: Vec<TokenType> = ast.binaries.iter().map(|binary| binary.op).collect();
+        assert_eq!(
+            ops,
+            [
+                TokenType::Mul,
+                TokenType::Plus,
+                TokenType::Minus,
+                TokenType::Pow
+            ]
+        );
+
+        let call = &ast.calls[0];
+        let args = ast.list(call.args);
+        assert_eq!(args[0].kind, NodeKind::Binary);
+        assert_eq!(args[1].kind, NodeKind::Array);
+        let callee = ast.atoms[call.callee.idx as usize];
+        assert_eq!((callee.kind, callee.text), (AtomKind::Ident, "f"));
+    }
+}
@@ -0,0 +1,178 @@
+//! Helpers for laying out human-readable output.
+
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+};
+
+use crate::lexer::{TokenSpan, Tokens};
+
+/// The token dump printed by [`Tokens`]' `Display` impl: one row per token
+/// with its position, type and text.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenDump<'t, 'a> {
+    tokens: &'t Tokens<'a>,
+    precedence: bool,
+}
+
+impl<'t, 'a> TokenDump<'t, 'a> {
+    pub fn new(tokens: &'t Tokens<'a>) -> Self {
+        Self {
+            tokens,
+            precedence: false,
+        }
+    }
+
+    /// Adds a column with the [precedence](crate::lexer::TokenType::precedence)
+    /// of binary operators, left blank for other tokens.
+    pub fn with_precedence(mut self) -> Self {
+        self.precedence = true;
+        self
+    }
+}
+
+impl fmt::Display for TokenDump<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens = self.tokens;
+
+        let mut line_dwidth = 0;
+        let mut col_dwidth = 0;
+        let mut type_dwidth = 0;
+        let mut prec_dwidth = 0;
+        for (&ty, span) in tokens.types.iter().zip(tokens.spans.iter()) {
+            line_dwidth = line_dwidth.max(digit_count(span.line));
+            col_dwidth = col_dwidth.max(digit_count(span.col));
+            type_dwidth = type_dwidth.max(format!("{ty:?}").len());
+            if let Some(prec) = ty.precedence() {
+                prec_dwidth = prec_dwidth.max(digit_count(prec.into()));
+            }
+        }
+
+        for (
+            &ty,
+            TokenSpan {
+                slice, line, col, ..
+            },
+        ) in tokens.types.iter().zip(tokens.spans.iter())
+        {
+            write!(
+                f,
+                "{line:>line_dwidth$}:{col:<col_dwidth$}   {:<type_dwidth$}   ",
+                format!("{ty:?}"),
+            )?;
+            if self.precedence {
+                match ty.precedence() {
+                    Some(prec) => write!(f, "{prec:>prec_dwidth$}   ")?,
+                    None => write!(f, "{:prec_dwidth$}   ", "")?,
+                }
+            }
+            writeln!(f, "{slice}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the token dump of [`Tokens`]' `Display` impl to `w` line by line,
+/// so large dumps never sit in memory as one string.
+pub fn format_tokens_to(w: impl Write, tokens: &Tokens) -> io::Result<()> {
+    format_dump_to(w, TokenDump::new(tokens))
+}
+
+/// Like [`format_tokens_to`], for a dump with extra columns.
+pub fn format_dump_to(w: impl Write, dump: TokenDump) -> io::Result<()> {
+    let mut w = BufWriter::new(w);
+    write!(w, "{dump}")?;
+    w.flush()
+}
+
+/// Number of decimal digits needed to print `n`. `0` takes one digit.
+pub fn digit_count(mut n: usize) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests_digit_count {
+    use crate::display::digit_count;
+
+    #[test]
+    fn exact() {
+        assert_eq!(digit_count(0), 1);
+        assert_eq!(digit_count(1), 1);
+        assert_eq!(digit_count(9), 1);
+        assert_eq!(digit_count(10), 2);
+        assert_eq!(digit_count(999), 3);
+        assert_eq!(digit_count(1000), 4);
+        assert_eq!(digit_count(usize::MAX), 20);
+    }
+}
+
+#[cfg(test)]
+mod tests_format_tokens_to {
+    use crate::{display::format_tokens_to, lexer::lex};
+
+    #[test]
+    fn round_trip() {
+        let tokens = lex("test", "x :: 5;\nloop {\n    f(x, \"y\");\n}\n");
+
+        let mut out = Vec::new();
+        format_tokens_to(&mut out, &tokens).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), tokens.types.len());
+        for ((line, ty), span) in lines
+            .iter()
+            .zip(tokens.types.iter())
+            .zip(tokens.spans.iter())
+        {
+            let (pos, rest) = line.trim_start().split_once(' ').unwrap();
+            let (pos_line, pos_col) = pos.split_once(':').unwrap();
+            assert_eq!(pos_line.parse::<usize>().unwrap(), span.line);
+            assert_eq!(pos_col.trim().parse::<usize>().unwrap(), span.col);
+
+            let rest = rest.trim_start();
+            let (name, slice) = rest.split_once(' ').unwrap_or((rest, ""));
+            assert_eq!(name, format!("{ty:?}"));
+            assert_eq!(slice.trim_start(), span.slice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_token_dump {
+    use crate::{display::TokenDump, lexer::lex};
+
+    #[test]
+    fn precedence_column() {
+        let tokens = lex("test", "a + b");
+        let dump = TokenDump::new(&tokens).with_precedence().to_string();
+        let rows: Vec<Vec<&str>> = dump
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+
+        assert_eq!(rows[0], ["1:0", "Ident", "a"]);
+        assert_eq!(rows[1], ["1:2", "Plus", "9", "+"]);
+        assert_eq!(rows[2], ["1:4", "Ident", "b"]);
+
+        // the column stays aligned when it's blank
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0].find('a'), lines[1].find('+'));
+    }
+
+    #[test]
+    fn no_precedence_by_default() {
+        let tokens = lex("test", "a + b");
+        let dump = TokenDump::new(&tokens).to_string();
+        assert_eq!(dump, tokens.to_string());
+
+        let plus: Vec<&str> = dump.lines().nth(1).unwrap().split_whitespace().collect();
+        assert_eq!(plus, ["1:2", "Plus", "+"]);
+    }
+}
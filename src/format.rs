@@ -0,0 +1,143 @@
+//! Re-emits code with canonical spacing.
+//!
+//! The formatter works on tokens only, so comments (which the lexer skips)
+//! are not preserved.
+
+use crate::lexer::{try_lex, LexError, TokenType};
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sep {
+    None,
+    Space,
+    Line,
+}
+
+pub fn format_source(code: &str) -> Result<String, LexError> {
+    let tokens = try_lex(code)?;
+
+    let mut out = String::with_capacity(code.len());
+    let mut depth = 0usize;
+    let mut prev: Option<(TokenType, bool)> = None;
+
+    for (&ty, span) in tokens.types.iter().zip(tokens.spans.iter()) {
+        if ty == TokenType::RBrace {
+            depth = depth.saturating_sub(1);
+        }
+
+        let unary = is_unary(ty, prev.map(|(ty, _)| ty));
+        match prev.map(|prev| separator(prev, ty)) {
+            None | Some(Sep::None) => {}
+            Some(Sep::Space) => out.push(' '),
+            Some(Sep::Line) => {
+                out.push('\n');
+                for _ in 0..depth {
+                    out.push_str(INDENT);
+                }
+            }
+        }
+        out.push_str(span.slice);
+
+        if ty == TokenType::LBrace {
+            depth += 1;
+        }
+        prev = Some((ty, unary));
+    }
+
+    if prev.is_some() {
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Whether a token that can be a prefix operator is one here, which is the
+/// case when it doesn't follow an operand.
+fn is_unary(ty: TokenType, prev: Option<TokenType>) -> bool {
+    use TokenType::*;
+
+    matches!(ty, Minus | Tilde | Ampersand | Not)
+        && !matches!(
+            prev,
+            Some(
+                Ident
+                    | Underscore
+                    | Num
+                    | String
+                    | Char
+                    | StringInterpEnd
+                    | RParens
+                    | RBracket
+                    | RBrace
+                    | Incr
+                    | Decr
+            )
+        )
+}
+
+fn separator((prev, prev_unary): (TokenType, bool), cur: TokenType) -> Sep {
+    use TokenType::*;
+
+    match (prev, cur) {
+        (LBrace, RBrace) => Sep::None,
+        (Semi | LBrace, _) | (_, RBrace) => Sep::Line,
+        (RBrace, Semi | Comma | RParens | RBracket | Else | Then) => Sep::Space,
+        (RBrace, _) => Sep::Line,
+
+        (_, Semi | Comma | RParens | RBracket | Dot | Incr | Decr) => Sep::None,
+        (LParens | LBracket | Dot, _) => Sep::None,
+        (StringInterpBeg | StringInterpMid, _) | (_, StringInterpMid | StringInterpEnd) => {
+            Sep::None
+        }
+        // attributes
+        (At, _) => Sep::None,
+        // `::` and `:=`
+        (Colon, Colon | Equal) => Sep::None,
+        // `not` is a keyword, so it needs the space
+        (Minus | Tilde | Ampersand, _) if prev_unary => Sep::None,
+        // calls and indexing
+        (Ident | String | Char | StringInterpEnd | RParens | RBracket | Fn, LParens | LBracket) => {
+            Sep::None
+        }
+        _ => Sep::Space,
+    }
+}
+
+#[cfg(test)]
+mod tests_format_source {
+    use crate::format::format_source;
+
+    #[test]
+    fn binary_operators() {
+        assert_eq!(format_source("x=1+2*3;").unwrap(), "x = 1 + 2 * 3;\n");
+        assert_eq!(format_source("-a**~b;").unwrap(), "-a ** ~b;\n");
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(format_source("f( a ,b )").unwrap(), "f(a, b)\n");
+        assert_eq!(
+            format_source("xs [ i ] . len ( )").unwrap(),
+            "xs[i].len()\n"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let code = "add::fn(a:i32)>-i32->{x:=a+1;x;}";
+        assert_eq!(
+            format_source(code).unwrap(),
+            "add :: fn(a : i32) >- i32 -> {\n    x := a + 1;\n    x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn preserves_literals() {
+        let code = "s := $\"{ x }  y\"   +  0x1F_ff  'ඞ';";
+        assert_eq!(
+            format_source(code).unwrap(),
+            "s := $\"{x}  y\" + 0x1F_ff 'ඞ';\n"
+        );
+    }
+}
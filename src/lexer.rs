@@ -1,8 +1,21 @@
-use std::{fmt, mem};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    iter, mem,
+    ops::Range,
+    str::FromStr,
+};
 
-use crate::arena::{ArenaVec, GIB};
+use crate::{
+    arena::{ArenaVec, GIB, MIB},
+    display::TokenDump,
+    lsp::{self, LspDiagnostic, LspSeverity},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
 pub enum TokenType {
     And, // and
     Or,  // or
@@ -64,6 +77,10 @@ pub enum TokenType {
     RBracket, // ]
     LBrace,   // {
     RBrace,   // }
+    At,       // @
+    Question, // ?
+
+    Underscore, // _
 
     String,
     StringInterpBeg,
@@ -72,25 +89,253 @@ pub enum TokenType {
     Char, // 'a'
     Ident,
     Num,
+    /// Code that couldn't be lexed, only produced when
+    /// [`LexerConfig::recover_errors`] is set
+    ///
+    /// Must stay the last variant, see [`Tokens::deserialize`] and
+    /// [`TokenType::COUNT`].
+    Error,
+}
+
+impl TokenType {
+    /// Number of token types. `ty as usize` is always less than this.
+    pub const COUNT: usize = TokenType::Error as usize + 1;
+
+    /// Whether this is one of the comparison operators.
+    pub const fn is_comparison(self) -> bool {
+        use TokenType::*;
+
+        match self {
+            Equals | NotEquals | LessThan | GreaterThan | LessEqual | GreaterEqual => true,
+
+            And | Or | Xor | Not | Feather | Arrow | Ampersand | Pipe | Caret | Tilde | LShift
+            | RShift | Incr | Decr | Plus | Minus | Mul | Div | Pow | Modulo | Pub | Packed
+            | Struct | Enum | Union | Fn | Defer | If | Then | Else | While | Do | Loop
+            | Continue | Break | Equal | Semi | Colon | Comma | Dot | LParens | RParens
+            | LBracket | RBracket | LBrace | RBrace | At | Question | Underscore | String
+            | StringInterpBeg | StringInterpMid | StringInterpEnd | Char | Ident | Num | Error => {
+                false
+            }
+        }
+    }
+
+    /// Whether this is one of the arithmetic operators.
+    pub const fn is_arithmetic(self) -> bool {
+        use TokenType::*;
+
+        match self {
+            Plus | Minus | Mul | Div | Pow | Modulo | Incr | Decr => true,
+
+            And | Or | Xor | Not | Equals | NotEquals | LessThan | GreaterThan | LessEqual
+            | GreaterEqual | Feather | Arrow | Ampersand | Pipe | Caret | Tilde | LShift
+            | RShift | Pub | Packed | Struct | Enum | Union | Fn | Defer | If | Then | Else
+            | While | Do | Loop | Continue | Break | Equal | Semi | Colon | Comma | Dot
+            | LParens | RParens | LBracket | RBracket | LBrace | RBrace | At | Question
+            | Underscore | String | StringInterpBeg | StringInterpMid | StringInterpEnd | Char
+            | Ident | Num | Error => false,
+        }
+    }
+
+    /// Whether this is one of the logical operators, which are keywords. The
+    /// bitwise operators are symbols instead.
+    pub const fn is_logical_keyword(self) -> bool {
+        use TokenType::*;
+
+        match self {
+            And | Or | Xor | Not => true,
+
+            Equals | NotEquals | LessThan | GreaterThan | LessEqual | GreaterEqual | Feather
+            | Arrow | Ampersand | Pipe | Caret | Tilde | LShift | RShift | Incr | Decr | Plus
+            | Minus | Mul | Div | Pow | Modulo | Pub | Packed | Struct | Enum | Union | Fn
+            | Defer | If | Then | Else | While | Do | Loop | Continue | Break | Equal | Semi
+            | Colon | Comma | Dot | LParens | RParens | LBracket | RBracket | LBrace | RBrace
+            | At | Question | Underscore | String | StringInterpBeg | StringInterpMid
+            | StringInterpEnd | Char | Ident | Num | Error => false,
+        }
+    }
+
+    /// How tightly this token binds as a binary operator, from `1` for `or`
+    /// up to `11` for `**`, or `None` if it isn't one. Matches the order the
+    /// parser uses.
+    pub const fn precedence(self) -> Option<u8> {
+        use TokenType::*;
+
+        let prec = match self {
+            Or => 1,
+            Xor => 2,
+            And => 3,
+            Equals | NotEquals | LessThan | GreaterThan | LessEqual | GreaterEqual => 4,
+            Pipe => 5,
+            Caret => 6,
+            Ampersand => 7,
+            LShift | RShift => 8,
+            Plus | Minus => 9,
+            Mul | Div | Modulo => 10,
+            Pow => 11,
+
+            Not | Feather | Arrow | Tilde | Incr | Decr | Pub | Packed | Struct | Enum | Union
+            | Fn | Defer | If | Then | Else | While | Do | Loop | Continue | Break | Equal
+            | Semi | Colon | Comma | Dot | LParens | RParens | LBracket | RBracket | LBrace
+            | RBrace | At | Question | Underscore | String | StringInterpBeg | StringInterpMid
+            | StringInterpEnd | Char | Ident | Num | Error => return None,
+        };
+
+        Some(prec)
+    }
+
+    /// Length in bytes of the text of tokens that are always written the
+    /// same way, or `None` for literals and identifiers.
+    pub const fn lexeme_len(self) -> Option<usize> {
+        use TokenType::*;
+
+        let lexeme = match self {
+            And => kw::AND,
+            Or => kw::OR,
+            Xor => kw::XOR,
+            Not => kw::NOT,
+            Pub => kw::PUB,
+            Packed => kw::PACKED,
+            Struct => kw::STRUCT,
+            Enum => kw::ENUM,
+            Union => kw::UNION,
+            Fn => kw::FN,
+            Defer => kw::DEFER,
+            If => kw::IF,
+            Then => kw::THEN,
+            Else => kw::ELSE,
+            While => kw::WHILE,
+            Do => kw::DO,
+            Loop => kw::LOOP,
+            Continue => kw::CONTINUE,
+            Break => kw::BREAK,
+
+            Equals => op::EQUALS,
+            NotEquals => op::NOT_EQUALS,
+            LessEqual => op::LESS_EQUAL,
+            GreaterEqual => op::GREATER_EQUAL,
+            Feather => op::FEATHER,
+            Arrow => op::ARROW,
+            LShift => op::L_SHIFT,
+            RShift => op::R_SHIFT,
+            Incr => op::INCR,
+            Decr => op::DECR,
+            Pow => op::POW,
+
+            LessThan | GreaterThan | Ampersand | Pipe | Caret | Tilde | Plus | Minus | Mul
+            | Div | Modulo | Equal | Semi | Colon | Comma | Dot | LParens | RParens | LBracket
+            | RBracket | LBrace | RBrace | At | Question | Underscore => return Some(1),
+
+            String | StringInterpBeg | StringInterpMid | StringInterpEnd | Char | Ident | Num
+            | Error => return None,
+        };
+
+        Some(lexeme.len())
+    }
+
+    /// The keyword or operator written as `lexeme`, if there is one.
+    pub fn from_lexeme(lexeme: &str) -> Option<TokenType> {
+        let mut state = LexState::new();
+        let scanned = next_token(lexeme.as_bytes(), &mut state)?.ok()?;
+        let ty = scanned.ty?;
+
+        (scanned.len == lexeme.len() && ty.lexeme_len() == Some(lexeme.len())).then_some(ty)
+    }
+}
+
+/// Error for strings that aren't the lexeme of a keyword or operator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownLexeme;
+
+impl fmt::Display for UnknownLexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not a keyword or operator")
+    }
+}
+
+impl FromStr for TokenType {
+    type Err = UnknownLexeme;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TokenType::from_lexeme(s).ok_or(UnknownLexeme)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TokenSpan<'a> {
     pub slice: &'a str,
+    /// Byte offset of the token in the code
+    pub offset: usize,
     pub line: usize,
     pub col: usize,
 }
 
 impl<'a> TokenSpan<'a> {
     #[inline]
-    pub const fn new(slice: &'a str, line: usize, col: usize) -> Self {
-        Self { slice, line, col }
+    pub const fn new(slice: &'a str, offset: usize, line: usize, col: usize) -> Self {
+        Self {
+            slice,
+            offset,
+            line,
+            col,
+        }
+    }
+
+    #[inline]
+    pub const fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.slice.len(),
+            line: self.line,
+            col: self.col,
+        }
+    }
+}
+
+/// Location of some code, independent of the code itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// Byte offset of the start
+    pub start: usize,
+    /// Byte offset right after the end
+    pub end: usize,
+    /// Line of the start
+    pub line: usize,
+    /// Column of the start
+    pub col: usize,
+}
+
+impl Span {
+    /// Span covering both spans and everything in between.
+    pub fn to(self, other: Span) -> Span {
+        let (first, last) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        Span {
+            start: first.start,
+            end: first.end.max(last.end),
+            line: first.line,
+            col: first.col,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
     }
 }
 
 #[derive(Debug)]
 pub struct Tokens<'a> {
-    /// The entire code file
+    /// The entire code file, without its byte order mark if it had one. Token
+    /// offsets are relative to this.
     pub code: &'a str,
     /// Sorted list containing the position of all line breaks
     pub line_breaks: ArenaVec<usize>,
@@ -98,645 +343,4233 @@ pub struct Tokens<'a> {
     pub spans: ArenaVec<TokenSpan<'a>>,
     /// Respective token types
     pub types: ArenaVec<TokenType>,
+    /// [`LexerConfig::tab_width`] the code was lexed with
+    tab_width: usize,
+    /// Where the code starts in the file it was taken from, as given to
+    /// [`try_lex_at_offset`]
+    base_offset: usize,
+    base_line: usize,
+    base_col: usize,
 }
 
-impl<'a> fmt::Display for Tokens<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fn log10(n: usize) -> usize {
-            (n as f64).log10().ceil() as usize
-        }
-
-        let line_dwidth = log10(self.line_breaks.len());
+impl<'a> Tokens<'a> {
+    fn new(code: &'a str) -> Self {
+        let addr_space_size = 64 * GIB;
 
-        let mut col_dwidth = 0;
-        let mut type_dwidth = 0;
-        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
-            col_dwidth = col_dwidth.max(log10(span.col));
-            type_dwidth = type_dwidth.max(format!("{ty:?}").len());
+        Self {
+            code,
+            line_breaks: ArenaVec::new(addr_space_size / 8),
+            spans: ArenaVec::new(addr_space_size),
+            types: ArenaVec::new(addr_space_size / mem::size_of::<TokenSpan>()),
+            tab_width: 1,
+            base_offset: 0,
+            base_line: 1,
+            base_col: 0,
         }
+    }
 
-        for (ty, TokenSpan { slice, line, col }) in self.types.iter().zip(self.spans.iter()) {
-            writeln!(
-                f,
-                "{line:>line_dwidth$}:{col:<col_dwidth$}   {:<type_dwidth$}   {slice}",
-                format!("{ty:?}"),
-                line_dwidth = line_dwidth,
-                col_dwidth = col_dwidth,
-                type_dwidth = type_dwidth,
-            )?;
+    /// Type suffix of the number token at `idx`, if it has one.
+    pub fn number_suffix(&self, idx: usize) -> Option<NumSuffix> {
+        if self.types[idx] != TokenType::Num {
+            return None;
         }
 
-        Ok(())
+        let slice = self.spans[idx].slice;
+        // `f32` and `f64` are plain digits in hex integers
+        let is_hex = slice.starts_with("0x") && !slice.contains(['p', 'P']);
+        NumSuffix::ALL
+            .iter()
+            .filter(|(suffix, _)| !(is_hex && matches!(suffix, NumSuffix::F32 | NumSuffix::F64)))
+            .find(|(_, text)| slice.ends_with(text))
+            .map(|&(suffix, _)| suffix)
     }
-}
-
-mod kw {
-    pub const CONTINUE: &[u8] = b"continue";
-    pub const PACKED: &[u8] = b"packed";
-    pub const STRUCT: &[u8] = b"struct";
-    pub const UNION: &[u8] = b"union";
-    pub const DEFER: &[u8] = b"defer";
-    pub const WHILE: &[u8] = b"while";
-    pub const BREAK: &[u8] = b"break";
-    pub const ENUM: &[u8] = b"enum";
-    pub const THEN: &[u8] = b"then";
-    pub const ELSE: &[u8] = b"else";
-    pub const LOOP: &[u8] = b"loop";
-    pub const AND: &[u8] = b"and";
-    pub const XOR: &[u8] = b"xor";
-    pub const NOT: &[u8] = b"not";
-    pub const PUB: &[u8] = b"pub";
-    pub const OR: &[u8] = b"or";
-    pub const FN: &[u8] = b"fn";
-    pub const IF: &[u8] = b"if";
-    pub const DO: &[u8] = b"do";
-}
 
-mod op {
-    pub const EQUALS: &[u8] = b"==";
-    pub const NOT_EQUALS: &[u8] = b"!=";
-    pub const LESS_EQUAL: &[u8] = b"<=";
-    pub const GREATER_EQUAL: &[u8] = b">=";
-    pub const FEATHER: &[u8] = b">-";
-    pub const ARROW: &[u8] = b"->";
-    pub const L_SHIFT: &[u8] = b"<<";
-    pub const R_SHIFT: &[u8] = b">>";
-    pub const INCR: &[u8] = b"++";
-    pub const DECR: &[u8] = b"--";
-    pub const POW: &[u8] = b"**";
-    pub const MODULO: &[u8] = b"%";
-    pub const LESS_THAN: &[u8] = b"<";
-    pub const GREATER_THAN: &[u8] = b">";
-    pub const AMPERSAND: &[u8] = b"&";
-    pub const PIPE: &[u8] = b"|";
-    pub const CARET: &[u8] = b"^";
-    pub const TILDE: &[u8] = b"~";
-    pub const PLUS: &[u8] = b"+";
-    pub const MINUS: &[u8] = b"-";
-    pub const MUL: &[u8] = b"*";
-    pub const DIV: &[u8] = b"/";
-    pub const EQUAL: &[u8] = b"=";
-    pub const SEMI: &[u8] = b";";
-    pub const COLON: &[u8] = b":";
-    pub const COMMA: &[u8] = b",";
-    pub const DOT: &[u8] = b".";
-    pub const L_PARENS: &[u8] = b"(";
-    pub const R_PARENS: &[u8] = b")";
-    pub const L_BRACKET: &[u8] = b"[";
-    pub const R_BRACKET: &[u8] = b"]";
-    pub const L_BRACE: &[u8] = b"{";
-    pub const R_BRACE: &[u8] = b"}";
-}
+    /// Base the number token at `idx` is written in, going by its prefix.
+    pub fn number_radix(&self, idx: usize) -> Option<Radix> {
+        if self.types[idx] != TokenType::Num {
+            return None;
+        }
 
-pub fn lex<'a>(file_name: &str, code: &'a str) -> Tokens<'a> {
-    let mut line = 1;
-    let mut line_start = code.as_ptr() as usize;
+        let radix = match self.spans[idx].slice.get(..2) {
+            Some("0x") => Radix::Hex,
+            Some("0o") => Radix::Oct,
+            Some("0b") => Radix::Bin,
+            _ => Radix::Dec,
+        };
+        Some(radix)
+    }
 
-    let addr_space_size = 64 * GIB;
+    /// Copy of the tokens keeping only those whose type matches `pred`.
+    pub fn filter(&self, pred: impl Fn(TokenType) -> bool) -> Tokens<'a> {
+        let mut tokens = Tokens::new(self.code);
+        tokens.tab_width = self.tab_width;
+        tokens.base_offset = self.base_offset;
+        tokens.base_line = self.base_line;
+        tokens.base_col = self.base_col;
 
-    let mut tokens = Tokens {
-        code,
-        line_breaks: ArenaVec::new(addr_space_size / 8),
-        spans: ArenaVec::new(addr_space_size),
-        types: ArenaVec::new(addr_space_size / mem::size_of::<TokenSpan>()),
-    };
+        for &offset in self.line_breaks.iter() {
+            tokens.line_breaks.add(offset);
+        }
+        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
+            if pred(ty) {
+                tokens.types.add(ty);
+                tokens.spans.add(span.clone());
+            }
+        }
 
-    let bcode = tokens.code.as_bytes();
-    let mut input = bcode;
-    while !input.is_empty() {
-        input = consume_token(file_name, input, &mut line, &mut line_start, &mut tokens);
+        tokens
     }
 
-    tokens
-}
+    /// Replaces the type of every token with `f(type, text)`, like to treat an
+    /// identifier as a keyword where the context calls for it.
+    pub fn map_types(&mut self, mut f: impl FnMut(TokenType, &str) -> TokenType) {
+        for (ty, span) in self.types.iter_mut().zip(self.spans.iter()) {
+            *ty = f(*ty, span.slice);
+        }
+    }
 
-/// Consume - in most cases - a single token.
-///
-/// Exceptions are made for special nestings, like interpolated strings and
-/// pairs of tokens that indicate a beginning and an end like parentheses,
-/// in which case it will recurse.
-fn consume_token<'a>(
-    file_name: &str,
-    mut input: &'a [u8],
-    line: &mut usize,
-    line_start: &mut usize,
-    tokens: &mut Tokens<'a>,
-) -> &'a [u8] {
-    let bcode = tokens.code.as_bytes();
-    let start_addr = bcode.as_ptr() as usize;
-
-    // save line breaks
-    while !input.is_empty() && input[0] == b'\n' {
-        let addr = input.as_ptr() as usize;
-        tokens.line_breaks.add(addr - start_addr);
-        input = &input[1..];
-        *line_start = input.as_ptr() as usize;
-        *line += 1;
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
     }
 
-    // ignore whitespace
-    while !input.is_empty() && input[0].is_ascii_whitespace() {
-        input = &input[1..];
+    /// Type, text, line and column of the last token.
+    pub fn last(&self) -> Option<(TokenType, &'a str, usize, usize)> {
+        let idx = self.types.len().checked_sub(1)?;
+        let span = &self.spans[idx];
+        Some((self.types[idx], span.slice, span.line, span.col))
     }
 
-    if input.is_empty() {
-        return input;
+    /// Length in bytes of the longest line, not counting its line break.
+    pub fn max_line_len(&self) -> usize {
+        let mut max = 0;
+        let mut line_start = 0;
+
+        for &line_break in self.line_breaks.iter() {
+            max = max.max(line_break - line_start);
+            line_start = line_break + 1;
+        }
+
+        max.max(self.code.len() - line_start)
     }
 
-    // ignore comments
-    if input.starts_with(b"//") {
-        input = &input[2..];
-        while input[0] != b'\n' {
-            input = &input[1..];
+    /// How often each token type occurs, indexed by `ty as usize`.
+    pub fn kind_histogram(&self) -> [usize; TokenType::COUNT] {
+        let mut counts = [0; TokenType::COUNT];
+        for &ty in self.types.iter() {
+            counts[ty as usize] += 1;
         }
-        return input;
+        counts
     }
 
-    // operators
-    {
-        let mut op_len;
-        let is_operator = 'op: {
-            op_len = 2;
-            if input.len() >= op_len {
-                let toktype = match &input[..op_len] {
-                    op::EQUALS => Some(TokenType::Equals),
-                    op::NOT_EQUALS => Some(TokenType::NotEquals),
-                    op::LESS_EQUAL => Some(TokenType::LessEqual),
-                    op::GREATER_EQUAL => Some(TokenType::GreaterEqual),
-                    op::FEATHER => Some(TokenType::Feather),
-                    op::ARROW => Some(TokenType::Arrow),
-                    op::L_SHIFT => Some(TokenType::LShift),
-                    op::R_SHIFT => Some(TokenType::RShift),
-                    op::INCR => Some(TokenType::Incr),
-                    op::DECR => Some(TokenType::Decr),
-                    op::POW => Some(TokenType::Pow),
-                    _ => None,
-                };
+    /// Line, column, type and text of every token: the rows of the token dump
+    /// printed by the `Display` impl.
+    pub fn debug_rows(&self) -> Vec<(usize, usize, TokenType, &'a str)> {
+        self.types
+            .iter()
+            .zip(self.spans.iter())
+            .map(|(&ty, span)| (span.line, span.col, ty, span.slice))
+            .collect()
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'op true;
-                }
-            }
+    /// Index of the first token starting on `line`, which starts at 1.
+    pub fn first_on_line(&self, line: usize) -> Option<usize> {
+        let spans = self.spans.as_slice();
+        let idx = spans.partition_point(|span| span.line < line);
+        spans.get(idx).filter(|span| span.line == line).map(|_| idx)
+    }
 
-            op_len = 1;
-            if input.len() >= op_len {
-                let toktype = match &input[..op_len] {
-                    op::MODULO => Some(TokenType::Modulo),
-                    op::LESS_THAN => Some(TokenType::LessThan),
-                    op::GREATER_THAN => Some(TokenType::GreaterThan),
-                    op::AMPERSAND => Some(TokenType::Ampersand),
-                    op::PIPE => Some(TokenType::Pipe),
-                    op::CARET => Some(TokenType::Caret),
-                    op::TILDE => Some(TokenType::Tilde),
-                    op::PLUS => Some(TokenType::Plus),
-                    op::MINUS => Some(TokenType::Minus),
-                    op::MUL => Some(TokenType::Mul),
-                    op::DIV => Some(TokenType::Div),
-                    op::EQUAL => Some(TokenType::Equal),
-                    op::SEMI => Some(TokenType::Semi),
-                    op::COLON => Some(TokenType::Colon),
-                    op::COMMA => Some(TokenType::Comma),
-                    op::DOT => Some(TokenType::Dot),
-                    op::L_PARENS => Some(TokenType::LParens),
-                    op::R_PARENS => Some(TokenType::RParens),
-                    op::L_BRACKET => Some(TokenType::LBracket),
-                    op::R_BRACKET => Some(TokenType::RBracket),
-                    op::L_BRACE => Some(TokenType::LBrace),
-                    op::R_BRACE => Some(TokenType::RBrace),
-                    _ => None,
-                };
+    /// Whether the token at `a` ends right where the token at `b` starts,
+    /// without anything in between.
+    pub fn adjacent(&self, a: usize, b: usize) -> bool {
+        let a = &self.spans[a];
+        a.offset + a.slice.len() == self.spans[b].offset
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'op true;
-                }
-            }
+    /// Line and column where the token at `idx` starts and where it ends, like
+    /// [`TokenSpan::line`] and [`TokenSpan::col`]. The end column is the one
+    /// right after the token's last character, with tabs in the token
+    /// advancing to the next multiple of [`LexerConfig::tab_width`].
+    pub fn span_lc(&self, idx: usize) -> ((usize, usize), (usize, usize)) {
+        let span = &self.spans[idx];
+        let start = (span.line, span.col);
 
-            false
+        let tab_width = self.tab_width.max(1);
+        let advance = |col: usize, text: &str| {
+            text.bytes().fold(col, |col, byte| match byte {
+                b'\t' => col + tab_width - col % tab_width,
+                _ => col + 1,
+            })
+        };
+        let end = match span.slice.rfind('\n') {
+            Some(last_break) => (
+                span.line + span.slice.matches('\n').count(),
+                advance(0, &span.slice[last_break + 1..]),
+            ),
+            None => (span.line, advance(span.col, span.slice)),
         };
 
-        if is_operator {
-            let col = input.as_ptr() as usize - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&input[..op_len]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            input = &input[op_len..];
-            return input;
-        }
+        (start, end)
     }
 
-    // interpolated strings
-    if input.starts_with(b"$\"") {
-        let mut is_valid = false;
+    /// Byte offset, line and column of a token in the file its code was taken
+    /// from. They only differ from the token's span for code lexed with
+    /// [`try_lex_at_offset`].
+    pub fn file_position(&self, idx: usize) -> (usize, usize, usize) {
+        let span = &self.spans[idx];
+        let col = if span.line == 1 {
+            span.col + self.base_col
+        } else {
+            span.col
+        };
+        (
+            span.offset + self.base_offset,
+            span.line + self.base_line - 1,
+            col,
+        )
+    }
 
-        let mut start_str_addr = input.as_ptr() as usize;
-        input = &input[2..];
+    /// Width of the leading whitespace of `line`, which starts at 1. Tabs
+    /// advance to the next multiple of [`LexerConfig::tab_width`]. Lines
+    /// without anything but whitespace have no indentation.
+    pub fn indentation(&self, line: usize) -> usize {
+        let breaks = self.line_breaks.as_slice();
+        let Some(line_start) = (match line {
+            0 => None,
+            1 => Some(0),
+            _ => breaks.get(line - 2).map(|&brk| brk + 1),
+        }) else {
+            return 0;
+        };
+        let line_end = breaks.get(line - 1).copied().unwrap_or(self.code.len());
+        let text = self.code[line_start..line_end].trim_end_matches('\r');
 
-        let mut has_interpolation = false;
-        while !input.is_empty() {
-            if input.starts_with(br#"\""#) || input.starts_with(br#"\{"#) {
-                input = &input[2..];
-                continue;
+        let tab_width = self.tab_width.max(1);
+        let mut width = 0;
+        for byte in text.bytes() {
+            match byte {
+                b' ' => width += 1,
+                b'\t' => width += tab_width - width % tab_width,
+                _ => return width,
             }
+        }
+        0
+    }
 
-            if input[0] == b'"' {
-                // end of string
-
-                is_valid = true;
-                input = &input[1..];
+    /// Lines, starting at 1, whose indentation has both tabs and spaces.
+    /// Lines without anything but whitespace are never reported.
+    pub fn mixed_indentation_lines(&self) -> Vec<usize> {
+        let line_starts = iter::once(0).chain(self.line_breaks.iter().map(|&brk| brk + 1));
+        let line_ends = self.line_breaks.iter().copied().chain([self.code.len()]);
 
-                let end_str_addr = input.as_ptr() as usize;
-                let start = start_str_addr - start_addr;
-                let end = end_str_addr - start_addr;
+        let mut lines = Vec::new();
+        for (idx, (start, end)) in line_starts.zip(line_ends).enumerate() {
+            let text = &self.code[start..end];
+            let indent_len = text.len() - text.trim_start_matches([' ', '\t']).len();
+            let indent = &text[..indent_len];
 
-                tokens.types.add(match has_interpolation {
-                    true => TokenType::StringInterpEnd,
-                    false => TokenType::String,
-                });
-                let col = bcode.as_ptr() as usize + start - *line_start;
-                let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-                tokens.spans.add(TokenSpan::new(slice, *line, col));
-                break;
-            } else if input[0] == b'{' {
-                // inside interpolated expression (we can consume tokens recursively)
+            let blank = text[indent_len..].trim_end_matches('\r').is_empty();
+            if !blank && indent.contains(' ') && indent.contains('\t') {
+                lines.push(idx + 1);
+            }
+        }
+        lines
+    }
 
-                input = &input[1..];
+    /// Whether any token is of the given type.
+    pub fn contains_type(&self, ty: TokenType) -> bool {
+        self.types.iter().any(|&t| t == ty)
+    }
 
-                let end_str_addr = input.as_ptr() as usize;
-                let start = start_str_addr - start_addr;
-                let end = end_str_addr - start_addr;
+    /// Indices of the tokens of the given type.
+    pub fn positions(&self, ty: TokenType) -> impl Iterator<Item = usize> + '_ {
+        self.types
+            .iter()
+            .enumerate()
+            .filter(move |(_, &t)| t == ty)
+            .map(|(idx, _)| idx)
+    }
 
-                tokens.types.add(match has_interpolation {
-                    true => TokenType::StringInterpMid,
-                    false => TokenType::StringInterpBeg,
-                });
-                let col = bcode.as_ptr() as usize + start - *line_start;
-                let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-                tokens.spans.add(TokenSpan::new(slice, *line, col));
+    /// Types and spans of the tokens parsers care about, which are all but
+    /// [`TokenType::Error`]s.
+    pub fn iter_significant(&self) -> impl Iterator<Item = (TokenType, &TokenSpan<'a>)> + '_ {
+        self.iter_skipping(&[TokenType::Error])
+    }
 
-                has_interpolation = true;
+    /// Types and spans of the tokens whose type isn't in `skip`.
+    pub fn iter_skipping<'s>(
+        &'s self,
+        skip: &'s [TokenType],
+    ) -> impl Iterator<Item = (TokenType, &'s TokenSpan<'a>)> + 's {
+        self.types
+            .iter()
+            .zip(self.spans.iter())
+            .filter(move |(ty, _)| !skip.contains(ty))
+            .map(|(&ty, span)| (ty, span))
+    }
 
-                while !input.is_empty() && input[0] != b'}' {
-                    input = consume_token(file_name, input, line, line_start, tokens);
-                }
-                if input.is_empty() {
-                    break;
-                }
+    /// Types and spans of the tokens from the last one to the first one.
+    pub fn iter_rev(&self) -> impl DoubleEndedIterator<Item = (TokenType, &TokenSpan<'a>)> + '_ {
+        self.types
+            .as_slice()
+            .iter()
+            .zip(self.spans.as_slice())
+            .rev()
+            .map(|(&ty, span)| (ty, span))
+    }
 
-                start_str_addr = input.as_ptr() as usize;
-                input = &input[1..];
-            } else if input[0] == b'\n' {
-                // strings support line breaks
+    /// Checks the invariants the lexer upholds, describing the first one that
+    /// is broken. Meant for tests and fuzzing.
+    pub fn validate_spans(&self) -> Result<(), String> {
+        if self.types.len() != self.spans.len() {
+            return Err(format!(
+                "{} types but {} spans",
+                self.types.len(),
+                self.spans.len()
+            ));
+        }
 
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
-            } else {
-                input = &input[1..];
+        let code_start = self.code.as_ptr() as usize;
+        let mut prev_line = 1;
+        for (idx, span) in self.spans.iter().enumerate() {
+            let slice_start = span.slice.as_ptr() as usize;
+            if slice_start != code_start + span.offset
+                || span.offset + span.slice.len() > self.code.len()
+            {
+                return Err(format!("span {idx} is not at its offset in the code"));
+            }
+            if span.line < prev_line {
+                return Err(format!(
+                    "span {idx} is on line {} after line {prev_line}",
+                    span.line
+                ));
             }
+            prev_line = span.line;
         }
 
-        if is_valid {
-            return input;
-        } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished interpolated string");
+        let mut prev_break = None;
+        for &offset in self.line_breaks.iter() {
+            if self.code.as_bytes().get(offset) != Some(&b'\n') {
+                return Err(format!("line break at {offset} is not a line break"));
+            }
+            if prev_break.is_some_and(|prev| prev >= offset) {
+                return Err(format!("line break at {offset} is out of order"));
+            }
+            prev_break = Some(offset);
         }
-    }
 
-    // strings
-    // todo: raw strings (like in Rust)
-    let (is_string, prefix): (bool, &[u8]) = if input.starts_with(b"b\"") {
-        (true, b"b\"")
-    } else if input.starts_with(b"c\"") {
-        (true, b"c\"")
-    } else if input[0] == b'"' {
-        (true, b"\"")
-    } else {
-        (false, b"")
-    };
+        Ok(())
+    }
 
-    if is_string {
-        let mut is_valid = false;
+    /// Index ranges of runs of three or more symbol operators in a row, like
+    /// `=+=`, which were likely meant to be a single operator. Runs of two are
+    /// common, as in `a + -b`.
+    pub fn suspicious_operator_runs(&self) -> Vec<Range<usize>> {
+        use TokenType::*;
 
-        let start_str_addr = input.as_ptr() as usize;
-        input = &input[prefix.len()..];
-        while !input.is_empty() {
-            if input.starts_with(br#"\""#) {
-                input = &input[2..];
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for (idx, &ty) in self.types.iter().enumerate() {
+            let is_operator = ty.is_comparison()
+                || ty.is_arithmetic()
+                || matches!(
+                    ty,
+                    Equal | Ampersand | Pipe | Caret | Tilde | LShift | RShift | Feather | Arrow
+                );
+            if is_operator {
                 continue;
             }
 
-            if input[0] == b'"' {
-                is_valid = true;
-                input = &input[1..];
-                break;
-            }
-
-            // strings support line breaks
-            if input[0] == b'\n' {
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
-            } else {
-                input = &input[1..];
+            if idx - start >= 3 {
+                runs.push(start..idx);
             }
+            start = idx + 1;
         }
+        if self.types.len() - start >= 3 {
+            runs.push(start..self.types.len());
+        }
+
+        runs
+    }
 
-        if is_valid {
-            let end_str_addr = input.as_ptr() as usize;
-            let start = start_str_addr - start_addr;
-            let end = end_str_addr - start_addr;
+    /// Writes the tokens in a compact binary format, for caching them along
+    /// with the code they were lexed from. See [`Tokens::deserialize`].
+    pub fn serialize(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&SERIALIZE_MAGIC)?;
+        w.write_all(&[SERIALIZE_VERSION])?;
+        write_varint(w, self.code.len())?;
 
-            tokens.types.add(TokenType::String);
-            let col = bcode.as_ptr() as usize + start - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            return input;
-        } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished string");
+        // offsets and lines only grow, so deltas keep them small
+        write_varint(w, self.line_breaks.len())?;
+        let mut prev = 0;
+        for &offset in self.line_breaks.iter() {
+            write_varint(w, offset - prev)?;
+            prev = offset;
+        }
+
+        write_varint(w, self.types.len())?;
+        let (mut prev_offset, mut prev_line) = (0, 1);
+        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
+            w.write_all(&[ty as u8])?;
+            write_varint(w, span.offset - prev_offset)?;
+            write_varint(w, span.slice.len())?;
+            write_varint(w, span.line - prev_line)?;
+            write_varint(w, span.col)?;
+            (prev_offset, prev_line) = (span.offset, span.line);
         }
+
+        Ok(())
     }
 
-    // chars
-    let (is_char, prefix): (bool, &[u8]) = if input.starts_with(b"b'") {
-        (true, b"b'")
-    } else if input[0] == b'\'' {
-        (true, b"'")
-    } else {
-        (false, b"")
-    };
+    /// Reads tokens written by [`Tokens::serialize`], taking their text from
+    /// `code`, which must be the code they were lexed from.
+    pub fn deserialize(r: &mut impl Read, code: &'a str) -> io::Result<Tokens<'a>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
 
-    if is_char {
-        let mut is_valid = false;
+        let mut header = [0; 5];
+        r.read_exact(&mut header)?;
+        if header[..4] != SERIALIZE_MAGIC {
+            return Err(invalid("Not serialized tokens"));
+        }
+        if header[4] != SERIALIZE_VERSION {
+            return Err(invalid("Unsupported serialized tokens version"));
+        }
+        let code = strip_bom(code);
+        if read_varint(r)? != code.len() {
+            return Err(invalid("Tokens were lexed from different code"));
+        }
 
-        let start_str_addr = input.as_ptr() as usize;
-        input = &input[prefix.len()..];
-        while !input.is_empty() {
-            if input.starts_with(br#"\'"#) {
-                input = &input[2..];
-                continue;
-            }
+        let tokens = Tokens::new(code);
 
-            if input[0] == b'\'' {
-                is_valid = true;
-                input = &input[1..];
-                break;
+        let mut offset: usize = 0;
+        for _ in 0..read_varint(r)? {
+            offset = offset
+                .checked_add(read_varint(r)?)
+                .ok_or_else(|| invalid("Line break outside of the code"))?;
+            if code.as_bytes().get(offset) != Some(&b'\n') {
+                return Err(invalid("Line break outside of the code"));
             }
+            tokens.line_breaks.add(offset);
+        }
 
-            // chars can handle line breaks (though they shouldn't be allowed)
-            if input[0] == b'\n' {
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
-            } else {
-                input = &input[1..];
+        let (mut offset, mut line): (usize, usize) = (0, 1);
+        for _ in 0..read_varint(r)? {
+            let mut ty = [0];
+            r.read_exact(&mut ty)?;
+            if ty[0] > TokenType::Error as u8 {
+                return Err(invalid("Unknown token type"));
             }
-        }
+            // SAFETY: `TokenType` is `repr(u8)` and `Error` is its last variant
+            let ty: TokenType = unsafe { mem::transmute(ty[0]) };
 
-        if is_valid {
-            let end_str_addr = input.as_ptr() as usize;
-            let start = start_str_addr - start_addr;
-            let end = end_str_addr - start_addr;
+            let outside = || invalid("Token outside of the code");
+            offset = offset.checked_add(read_varint(r)?).ok_or_else(outside)?;
+            let end = offset.checked_add(read_varint(r)?).ok_or_else(outside)?;
+            line = line
+                .checked_add(read_varint(r)?)
+                .ok_or_else(|| invalid("Token line out of range"))?;
+            let col = read_varint(r)?;
+            let slice = code.get(offset..end).ok_or_else(outside)?;
 
-            tokens.types.add(TokenType::Char);
-            let col = bcode.as_ptr() as usize + start - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            return input;
-        } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished char");
+            tokens.types.add(ty);
+            tokens.spans.add(TokenSpan::new(slice, offset, line, col));
         }
+
+        Ok(tokens)
     }
 
-    // identifiers
-    if matches!(input[0], b'_' | b'A'..=b'Z' | b'a'..=b'z') {
-        let start_ident_addr = input.as_ptr() as usize;
+    /// Whether both token streams have the same types and text, regardless of
+    /// where the tokens are.
+    pub fn same_tokens(&self, other: &Tokens) -> bool {
+        self.types.as_slice() == other.types.as_slice()
+            && self
+                .spans
+                .iter()
+                .zip(other.spans.iter())
+                .all(|(a, b)| a.slice == b.slice)
+    }
 
-        input = &input[1..];
-        while matches!(input[0], b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9') {
-            input = &input[1..];
-        }
+    /// Indices of the tokens overlapping a byte range, like the range of an
+    /// edit. The result is empty if the range only touches whitespace or
+    /// comments, and starts at the first token after it.
+    ///
+    /// An empty range, like that of an insertion, includes the tokens right
+    /// before and after it, since inserted text can join them.
+    pub fn tokens_in_range(&self, byte_range: Range<usize>) -> Range<usize> {
+        let spans = self.spans.as_slice();
+        let (start, end) = if byte_range.is_empty() {
+            let at = byte_range.start;
+            (
+                spans.partition_point(|span| span.offset + span.slice.len() < at),
+                spans.partition_point(|span| span.offset <= at),
+            )
+        } else {
+            (
+                spans.partition_point(|span| span.offset + span.slice.len() <= byte_range.start),
+                spans.partition_point(|span| span.offset < byte_range.end),
+            )
+        };
+        start..end.max(start)
+    }
 
-        let end_ident_addr = input.as_ptr() as usize;
-        let start = start_ident_addr - start_addr;
-        let end = end_ident_addr - start_addr;
+    /// Code around the token at `idx`: up to `radius` bytes before and after
+    /// it, without going past the lines the token is on.
+    pub fn context(&self, idx: usize, radius: usize) -> &'a str {
+        let span = &self.spans[idx];
+        let breaks = self.line_breaks.as_slice();
+        let token_end = span.offset + span.slice.len();
 
-        let col = bcode.as_ptr() as usize + start - *line_start;
-        let ident_slice = &bcode[start..end];
+        let line_start = match breaks.partition_point(|&brk| brk < span.offset) {
+            0 => 0,
+            line => breaks[line - 1] + 1,
+        };
+        let line_end = breaks
+            .get(breaks.partition_point(|&brk| brk < token_end))
+            .copied()
+            .unwrap_or(self.code.len());
 
-        let mut token_len;
-        let is_keyword = 'kw: {
-            // keywords
+        let mut start = span.offset.saturating_sub(radius).max(line_start);
+        while !self.code.is_char_boundary(start) {
+            start += 1;
+        }
+        let mut end = (token_end + radius).min(line_end);
+        while !self.code.is_char_boundary(end) {
+            end -= 1;
+        }
 
-            token_len = 8;
-            if ident_slice.len() >= token_len {
-                let toktype = if &ident_slice[..token_len] == kw::CONTINUE {
-                    Some(TokenType::Continue)
-                } else {
-                    None
-                };
+        &self.code[start..end]
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
+    /// Checks that every `then` follows an `if` and every `else` follows an
+    /// `if`/`then`, without parsing. Keywords only pair up with others in the
+    /// same braces, so an `if` inside a block can't take a `then` after it.
+    pub fn check_control_flow(&self) -> Result<(), ControlFlowError> {
+        /// `if`s still waiting for their `then`, and `then`s still open to an
+        /// `else`, in one pair of braces
+        #[derive(Default)]
+        struct Frame {
+            ifs: usize,
+            thens: usize,
+        }
+
+        let mut stack = vec![Frame::default()];
+        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
+            let (line, col) = (span.line, span.col + 1);
+            match ty {
+                TokenType::LBrace => stack.push(Frame::default()),
+                TokenType::RBrace if stack.len() > 1 => {
+                    stack.pop();
+                }
+                TokenType::If => stack.last_mut().unwrap().ifs += 1,
+                TokenType::Then => {
+                    let frame = stack.last_mut().unwrap();
+                    if frame.ifs == 0 {
+                        return Err(ControlFlowError::ThenWithoutIf { line, col });
+                    }
+                    frame.ifs -= 1;
+                    frame.thens += 1;
+                }
+                TokenType::Else => {
+                    let frame = stack.last_mut().unwrap();
+                    if frame.thens == 0 {
+                        return Err(ControlFlowError::ElseWithoutThen { line, col });
+                    }
+                    frame.thens -= 1;
                 }
+                _ => {}
             }
+        }
 
-            token_len = 6;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::PACKED => Some(TokenType::Packed),
-                    kw::STRUCT => Some(TokenType::Struct),
-                    _ => None,
-                };
+        Ok(())
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+    /// Code covered by a range of tokens, including anything in between them.
+    pub fn merge_span(&self, range: Range<usize>) -> &'a str {
+        if range.is_empty() {
+            return "";
+        }
 
-            token_len = 5;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::UNION => Some(TokenType::Union),
-                    kw::DEFER => Some(TokenType::Defer),
-                    kw::WHILE => Some(TokenType::While),
-                    kw::BREAK => Some(TokenType::Break),
-                    _ => None,
-                };
+        let first = &self.spans[range.start];
+        let last = &self.spans[range.end - 1];
+        &self.code[first.offset..last.offset + last.slice.len()]
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+    /// Types and spans of the tokens along with the text of the line each one
+    /// starts on, without its line break. The text is looked up once per line.
+    pub fn iter_with_line(
+        &self,
+    ) -> impl Iterator<Item = (TokenType, &TokenSpan<'a>, &'a str)> + '_ {
+        let mut cached: Option<(usize, &'a str)> = None;
 
-            token_len = 4;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::ENUM => Some(TokenType::Enum),
-                    kw::THEN => Some(TokenType::Then),
-                    kw::ELSE => Some(TokenType::Else),
-                    kw::LOOP => Some(TokenType::Loop),
-                    _ => None,
+        self.types
+            .iter()
+            .zip(self.spans.iter())
+            .map(move |(&ty, span)| {
+                let text = match cached {
+                    Some((line, text)) if line == span.line => text,
+                    _ => {
+                        let text = self.line_text(span.line);
+                        cached = Some((span.line, text));
+                        text
+                    }
                 };
+                (ty, span, text)
+            })
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
+    /// Byte offset in `line_text` of a column counted like
+    /// [`TokenSpan::col`], which expands tabs to [`LexerConfig::tab_width`].
+    fn byte_col(&self, line_text: &str, col: usize) -> usize {
+        let tab_width = self.tab_width.max(1);
+        let mut width = 0;
+        for (idx, byte) in line_text.bytes().enumerate() {
+            if width >= col {
+                return idx;
             }
+            width += match byte {
+                b'\t' => tab_width - width % tab_width,
+                _ => 1,
+            };
+        }
+        line_text.len()
+    }
 
-            token_len = 3;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::AND => Some(TokenType::And),
-                    kw::XOR => Some(TokenType::Xor),
-                    kw::NOT => Some(TokenType::Not),
-                    kw::PUB => Some(TokenType::Pub),
-                    _ => None,
-                };
+    /// Text of `line`, which starts at 1, without its line break.
+    fn line_text(&self, line: usize) -> &'a str {
+        let breaks = self.line_breaks.as_slice();
+        let start = match line {
+            1 => 0,
+            _ => breaks[line - 2] + 1,
+        };
+        let end = breaks.get(line - 1).copied().unwrap_or(self.code.len());
+        self.code[start..end].trim_end_matches('\r')
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+    /// Contents of the string token at `idx` without its prefix and quotes.
+    /// A backslash right before a line break joins the lines: neither the
+    /// backslash nor the line break are part of the value. `\u{...}` escapes
+    /// are replaced with the character they stand for. Other escapes are kept
+    /// as written.
+    ///
+    /// Only strings can be continued this way; statements already span lines
+    /// freely, since line breaks are whitespace.
+    pub fn string_value(&self, idx: usize) -> Option<Result<Cow<'a, str>, EscapeError>> {
+        if self.types[idx] != TokenType::String {
+            return None;
+        }
 
-            token_len = 2;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::OR => Some(TokenType::Or),
-                    kw::FN => Some(TokenType::Fn),
-                    kw::IF => Some(TokenType::If),
-                    kw::DO => Some(TokenType::Do),
-                    _ => None,
-                };
+        let span = &self.spans[idx];
+        let slice = span.slice;
+        if slice.starts_with("\"\"\"") {
+            return self
+                .block_string_value(idx)
+                .map(|value| Ok(Cow::Owned(value)));
+        }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
+        let inner_start = slice.find('"')? + 1;
+        let inner = &slice[inner_start..slice.len() - 1];
+        if !inner.contains("\\\n") && !inner.contains("\\\r\n") && !inner.contains("\\u") {
+            return Some(Ok(Cow::Borrowed(inner)));
+        }
+
+        let mut value = String::with_capacity(inner.len());
+        let mut rest = inner;
+        while let Some(c) = rest.chars().next() {
+            let offset = span.offset + inner_start + inner.len() - rest.len();
+            rest = &rest[c.len_utf8()..];
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+
+            if let Some(after) = rest.strip_prefix('\n').or(rest.strip_prefix("\r\n")) {
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('u') {
+                match unicode_escape(after, offset) {
+                    Ok((escaped, len)) => {
+                        value.push(escaped);
+                        rest = &after[len..];
+                    }
+                    Err(err) => return Some(Err(err)),
                 }
+            } else if let Some(escaped) = rest.chars().next() {
+                value.push(c);
+                value.push(escaped);
+                rest = &rest[escaped.len_utf8()..];
             }
+        }
 
-            false
-        };
+        Some(Ok(Cow::Owned(value)))
+    }
 
-        if !is_keyword {
-            tokens.types.add(TokenType::Ident);
+    /// Character of the char token at `idx`. Escapes are decoded: `\u{...}`,
+    /// `\n`, `\r`, `\t`, `\0`, `\\`, `\'` and `\"`.
+    pub fn char_value(&self, idx: usize) -> Option<Result<char, EscapeError>> {
+        if self.types[idx] != TokenType::Char {
+            return None;
         }
 
-        let slice = unsafe { std::str::from_utf8_unchecked(ident_slice) };
-        tokens.spans.add(TokenSpan::new(slice, *line, col));
-        return input;
+        let span = &self.spans[idx];
+        let slice = span.slice;
+        let inner_start = slice.find('\'')? + 1;
+        let inner = &slice[inner_start..slice.len() - 1];
+        let offset = span.offset + inner_start;
+
+        let (value, len) = match inner.strip_prefix('\\') {
+            Some(escape) => match escape.chars().next() {
+                Some('u') => match unicode_escape(&escape[1..], offset) {
+                    Ok((value, len)) => (value, len + 2),
+                    Err(err) => return Some(Err(err)),
+                },
+                Some('n') => ('\n', 2),
+                Some('r') => ('\r', 2),
+                Some('t') => ('\t', 2),
+                Some('0') => ('\0', 2),
+                Some(c @ ('\\' | '\'' | '"')) => (c, 2),
+                _ => return Some(Err(EscapeError::NotOneChar { offset })),
+            },
+            None => match inner.chars().next() {
+                Some(c) => (c, c.len_utf8()),
+                None => return Some(Err(EscapeError::NotOneChar { offset })),
+            },
+        };
+
+        if len != inner.len() {
+            return Some(Err(EscapeError::NotOneChar { offset }));
+        }
+        Some(Ok(value))
     }
 
-    // numbers
-    if input[0].is_ascii_digit() {
-        let start_ident_addr = input.as_ptr() as usize;
+    /// Contents of the `"""` string token at `idx`. The line break after the
+    /// opening quotes and the whitespace-only line before the closing quotes
+    /// are dropped, as is the leading indentation common to all lines.
+    pub fn block_string_value(&self, idx: usize) -> Option<String> {
+        let slice = self.spans[idx].slice;
+        if self.types[idx] != TokenType::String || !slice.starts_with("\"\"\"") {
+            return None;
+        }
 
-        if input.starts_with(b"0x") {
-            // hex literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F') {
-                input = &input[1..];
-            }
-        } else if input.starts_with(b"0o") {
-            // octal literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'7') {
-                input = &input[1..];
-            }
-        } else if input.starts_with(b"0b") {
-            // binary literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'1') {
-                input = &input[1..];
+        let mut inner = &slice[3..slice.len() - 3];
+        inner = inner
+            .strip_prefix('\n')
+            .or(inner.strip_prefix("\r\n"))
+            .unwrap_or(inner);
+        if let Some(last_break) = inner.rfind('\n') {
+            if inner[last_break..].trim().is_empty() {
+                inner = inner[..last_break].trim_end_matches('\r');
             }
-        } else {
-            // decimal and floating literals
+        }
 
-            // whole part
-            input = &input[1..];
-            while matches!(input[0], b'_' | b'0'..=b'9') {
-                input = &input[1..];
-            }
+        let indent = inner
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
 
-            // fractional part
-            if input[0] == b'.' {
-                input = &input[1..];
-                while matches!(input[0], b'_' | b'0'..=b'9') {
-                    input = &input[1..];
-                }
-            }
+        let lines: Vec<&str> = inner
+            .lines()
+            .map(|line| line.get(indent..).unwrap_or("").trim_end_matches('\r'))
+            .collect();
+        Some(lines.join("\n"))
+    }
+}
 
-            // exponent
-            if matches!(input[0], b'e' | b'E') {
-                input = &input[1..];
-                if matches!(input[0], b'+' | b'-') {
-                    input = &input[1..];
-                }
-                while matches!(input[0], b'_' | b'0'..=b'9') {
-                    input = &input[1..];
-                }
-            }
+impl<'a> fmt::Display for Tokens<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        TokenDump::new(self).fmt(f)
+    }
+}
+
+const SERIALIZE_MAGIC: [u8; 4] = *b"SUSt";
+/// Has to be bumped whenever [`TokenType`] changes, since token types are
+/// serialized as their discriminant.
+const SERIALIZE_VERSION: u8 = 1;
+
+/// Writes `value` as a LEB128 varint: 7 bits per byte, low bits first, with
+/// the high bit set on all but the last byte.
+fn write_varint(w: &mut impl Write, mut value: usize) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<usize> {
+    let mut value = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Varint is too long",
+    ))
+}
+
+/// A difference between two token streams found by [`diff_tokens`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenDiff<'a> {
+    Type {
+        idx: usize,
+        a: TokenType,
+        b: TokenType,
+    },
+    Text {
+        idx: usize,
+        a: &'a str,
+        b: &'a str,
+    },
+    /// Line and column of a token
+    Position {
+        idx: usize,
+        a: (usize, usize),
+        b: (usize, usize),
+    },
+    /// Numbers of tokens, when they differ. Only the tokens both streams have
+    /// are compared.
+    Len {
+        a: usize,
+        b: usize,
+    },
+}
+
+/// Differences between the tokens at each index of two token streams. A token
+/// can differ in more than one way.
+pub fn diff_tokens<'a>(a: &Tokens<'a>, b: &Tokens<'a>) -> Vec<TokenDiff<'a>> {
+    let mut diffs = Vec::new();
+
+    let pairs = a
+        .types
+        .iter()
+        .zip(a.spans.iter())
+        .zip(b.types.iter().zip(b.spans.iter()));
+    for (idx, ((&a_ty, a_span), (&b_ty, b_span))) in pairs.enumerate() {
+        if a_ty != b_ty {
+            diffs.push(TokenDiff::Type {
+                idx,
+                a: a_ty,
+                b: b_ty,
+            });
+        }
+        if a_span.slice != b_span.slice {
+            diffs.push(TokenDiff::Text {
+                idx,
+                a: a_span.slice,
+                b: b_span.slice,
+            });
+        }
+        if (a_span.line, a_span.col) != (b_span.line, b_span.col) {
+            diffs.push(TokenDiff::Position {
+                idx,
+                a: (a_span.line, a_span.col),
+                b: (b_span.line, b_span.col),
+            });
+        }
+    }
+
+    if a.types.len() != b.types.len() {
+        diffs.push(TokenDiff::Len {
+            a: a.types.len(),
+            b: b.types.len(),
+        });
+    }
+
+    diffs
+}
+
+mod kw {
+    use super::TokenType;
+
+    pub const CONTINUE: &[u8] = b"continue";
+    pub const PACKED: &[u8] = b"packed";
+    pub const STRUCT: &[u8] = b"struct";
+    pub const UNION: &[u8] = b"union";
+    pub const DEFER: &[u8] = b"defer";
+    pub const WHILE: &[u8] = b"while";
+    pub const BREAK: &[u8] = b"break";
+    pub const ENUM: &[u8] = b"enum";
+    pub const THEN: &[u8] = b"then";
+    pub const ELSE: &[u8] = b"else";
+    pub const LOOP: &[u8] = b"loop";
+    pub const AND: &[u8] = b"and";
+    pub const XOR: &[u8] = b"xor";
+    pub const NOT: &[u8] = b"not";
+    pub const PUB: &[u8] = b"pub";
+    pub const OR: &[u8] = b"or";
+    pub const FN: &[u8] = b"fn";
+    pub const IF: &[u8] = b"if";
+    pub const DO: &[u8] = b"do";
+
+    pub const ALL: &[(&[u8], TokenType)] = &[
+        (CONTINUE, TokenType::Continue),
+        (PACKED, TokenType::Packed),
+        (STRUCT, TokenType::Struct),
+        (UNION, TokenType::Union),
+        (DEFER, TokenType::Defer),
+        (WHILE, TokenType::While),
+        (BREAK, TokenType::Break),
+        (ENUM, TokenType::Enum),
+        (THEN, TokenType::Then),
+        (ELSE, TokenType::Else),
+        (LOOP, TokenType::Loop),
+        (AND, TokenType::And),
+        (XOR, TokenType::Xor),
+        (NOT, TokenType::Not),
+        (PUB, TokenType::Pub),
+        (OR, TokenType::Or),
+        (FN, TokenType::Fn),
+        (IF, TokenType::If),
+        (DO, TokenType::Do),
+    ];
+}
+
+mod op {
+    // operators starting with another operator's byte
+    pub const EQUALS: &[u8] = b"==";
+    pub const NOT_EQUALS: &[u8] = b"!=";
+    pub const LESS_EQUAL: &[u8] = b"<=";
+    pub const GREATER_EQUAL: &[u8] = b">=";
+    pub const FEATHER: &[u8] = b">-";
+    pub const ARROW: &[u8] = b"->";
+    pub const L_SHIFT: &[u8] = b"<<";
+    pub const R_SHIFT: &[u8] = b">>";
+    pub const INCR: &[u8] = b"++";
+    pub const DECR: &[u8] = b"--";
+    pub const POW: &[u8] = b"**";
+
+    // single byte operators
+    pub const MODULO: u8 = b'%';
+    pub const LESS_THAN: u8 = b'<';
+    pub const GREATER_THAN: u8 = b'>';
+    pub const AMPERSAND: u8 = b'&';
+    pub const PIPE: u8 = b'|';
+    pub const CARET: u8 = b'^';
+    pub const TILDE: u8 = b'~';
+    pub const PLUS: u8 = b'+';
+    pub const MINUS: u8 = b'-';
+    pub const MUL: u8 = b'*';
+    pub const DIV: u8 = b'/';
+    pub const EQUAL: u8 = b'=';
+    pub const SEMI: u8 = b';';
+    pub const COLON: u8 = b':';
+    pub const COMMA: u8 = b',';
+    pub const DOT: u8 = b'.';
+    pub const L_PARENS: u8 = b'(';
+    pub const R_PARENS: u8 = b')';
+    pub const L_BRACKET: u8 = b'[';
+    pub const R_BRACKET: u8 = b']';
+    pub const L_BRACE: u8 = b'{';
+    pub const R_BRACE: u8 = b'}';
+    pub const AT: u8 = b'@';
+    pub const QUESTION: u8 = b'?';
+}
+
+/// Type suffix of a number literal, like the `u8` in `255u8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumSuffix {
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+}
+
+impl NumSuffix {
+    const ALL: [(NumSuffix, &'static str); 12] = [
+        (NumSuffix::I8, "i8"),
+        (NumSuffix::I16, "i16"),
+        (NumSuffix::I32, "i32"),
+        (NumSuffix::I64, "i64"),
+        (NumSuffix::I128, "i128"),
+        (NumSuffix::U8, "u8"),
+        (NumSuffix::U16, "u16"),
+        (NumSuffix::U32, "u32"),
+        (NumSuffix::U64, "u64"),
+        (NumSuffix::U128, "u128"),
+        (NumSuffix::F32, "f32"),
+        (NumSuffix::F64, "f64"),
+    ];
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(_, text)| text.as_bytes() == bytes)
+            .map(|&(suffix, _)| suffix)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        Self::ALL[self as usize].1
+    }
+}
+
+/// Base of a number literal, from its `0x`, `0o` or `0b` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Radix {
+    Bin,
+    Oct,
+    Dec,
+    Hex,
+}
+
+impl Radix {
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Bin => 2,
+            Radix::Oct => 8,
+            Radix::Dec => 10,
+            Radix::Hex => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    UnfinishedInterpString {
+        line: usize,
+        col: usize,
+    },
+    /// A string missing its closing quote. Points at the opening quote, not
+    /// at the end of the code where the string runs out.
+    UnfinishedString {
+        line: usize,
+        col: usize,
+    },
+    UnfinishedChar {
+        line: usize,
+        col: usize,
+    },
+    CannotParseToken {
+        line: usize,
+        col: usize,
+    },
+    InvalidUtf8 {
+        line: usize,
+        col: usize,
+    },
+    InvalidNumSuffix {
+        line: usize,
+        col: usize,
+    },
+    MissingHexExponent {
+        line: usize,
+        col: usize,
+    },
+    UnfinishedComment {
+        line: usize,
+        col: usize,
+    },
+    /// A control character other than whitespace, like a null byte
+    ControlChar {
+        byte: u8,
+        line: usize,
+        col: usize,
+    },
+}
+
+impl LexError {
+    /// Line and column of the error, both starting at 1.
+    pub fn position(&self) -> (usize, usize) {
+        match *self {
+            LexError::UnfinishedInterpString { line, col }
+            | LexError::UnfinishedString { line, col }
+            | LexError::UnfinishedChar { line, col }
+            | LexError::CannotParseToken { line, col }
+            | LexError::InvalidUtf8 { line, col }
+            | LexError::InvalidNumSuffix { line, col }
+            | LexError::MissingHexExponent { line, col }
+            | LexError::UnfinishedComment { line, col }
+            | LexError::ControlChar { line, col, .. } => (line, col),
         }
+    }
+
+    /// Moves the error from a fragment of code to where the fragment starts,
+    /// like in [`try_lex_at_offset`].
+    fn shifted(mut self, base_line: usize, base_col: usize) -> Self {
+        let (LexError::UnfinishedInterpString { line, col }
+        | LexError::UnfinishedString { line, col }
+        | LexError::UnfinishedChar { line, col }
+        | LexError::CannotParseToken { line, col }
+        | LexError::InvalidUtf8 { line, col }
+        | LexError::InvalidNumSuffix { line, col }
+        | LexError::MissingHexExponent { line, col }
+        | LexError::UnfinishedComment { line, col }
+        | LexError::ControlChar { line, col, .. }) = &mut self;
 
-        let end_ident_addr = input.as_ptr() as usize;
-        let start = start_ident_addr - start_addr;
-        let end = end_ident_addr - start_addr;
+        if *line == 1 {
+            *col += base_col;
+        }
+        *line += base_line - 1;
+        self
+    }
 
-        tokens.types.add(TokenType::Num);
-        let col = bcode.as_ptr() as usize + start - *line_start;
-        let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-        tokens.spans.add(TokenSpan::new(slice, *line, col));
-        return input;
+    pub fn message(&self) -> &'static str {
+        match self {
+            LexError::UnfinishedInterpString { .. } => "Unfinished interpolated string",
+            LexError::UnfinishedString { .. } => "Unfinished string",
+            LexError::UnfinishedChar { .. } => "Unfinished char",
+            LexError::CannotParseToken { .. } => "Cannot parse token",
+            LexError::InvalidUtf8 { .. } => "Invalid UTF-8",
+            LexError::InvalidNumSuffix { .. } => "Invalid number suffix",
+            LexError::MissingHexExponent { .. } => "Hex float without `p` exponent",
+            LexError::UnfinishedComment { .. } => "Unfinished block comment",
+            LexError::ControlChar { .. } => "Control character",
+        }
     }
 
-    // Special recursions (parentheses, etc.)
-    if input[0] == b'(' {
-        let start_str_addr = input.as_ptr() as usize;
+    /// Converts the error into an LSP diagnostic for the code of `tokens`,
+    /// covering the character it points at. The tokens lexed before the
+    /// error, like those returned by [`try_lex_bytes`], will do.
+    pub fn to_lsp_diagnostic(&self, tokens: &Tokens) -> LspDiagnostic {
+        let (line, col) = self.position();
+        let line_text = tokens.code.split('\n').nth(line - 1).unwrap_or_default();
+        let col = tokens.byte_col(line_text, col - 1);
 
-        while !input.is_empty() && input[0] != b')' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+        LspDiagnostic {
+            range: lsp::char_range(tokens.code, line, col),
+            severity: LspSeverity::Error,
+            message: self.message().to_string(),
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed parenthesis");
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.position();
+        write!(f, "{line}:{col}: {}", self.message())?;
+        if let LexError::ControlChar { byte, .. } = self {
+            write!(f, " 0x{byte:02X}")?;
         }
-    } else if input[0] == b'[' {
-        let start_str_addr = input.as_ptr() as usize;
+        Ok(())
+    }
+}
 
-        while !input.is_empty() && input[0] != b']' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+/// A `then` or `else` found by [`Tokens::check_control_flow`] that doesn't
+/// belong to an `if`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlFlowError {
+    ThenWithoutIf { line: usize, col: usize },
+    ElseWithoutThen { line: usize, col: usize },
+}
+
+impl ControlFlowError {
+    /// Line and column of the keyword, both starting at 1.
+    pub fn position(&self) -> (usize, usize) {
+        match *self {
+            ControlFlowError::ThenWithoutIf { line, col }
+            | ControlFlowError::ElseWithoutThen { line, col } => (line, col),
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed bracket");
+    }
+}
+
+impl fmt::Display for ControlFlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.position();
+        let message = match self {
+            ControlFlowError::ThenWithoutIf { .. } => "`then` without `if`",
+            ControlFlowError::ElseWithoutThen { .. } => "`else` without `if`/`then`",
+        };
+        write!(f, "{line}:{col}: {message}")
+    }
+}
+
+/// An invalid escape found by [`Tokens::string_value`] or
+/// [`Tokens::char_value`]. Offsets are byte offsets in the code of the
+/// escape's backslash, or of the char literal's contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EscapeError {
+    /// `\u` not followed by 1 to 6 hex digits in braces
+    MalformedUnicode { offset: usize },
+    /// `\u{...}` above `10FFFF`, the largest code point
+    OutOfRange { offset: usize, code_point: u32 },
+    /// `\u{...}` of a UTF-16 surrogate, which can't be a character on its own
+    Surrogate { offset: usize, code_point: u32 },
+    /// A char literal that is empty or holds more than one character
+    NotOneChar { offset: usize },
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscapeError::MalformedUnicode { offset } => {
+                write!(
+                    f,
+                    "{offset}: `\\u` must be followed by 1 to 6 hex digits in braces"
+                )
+            }
+            EscapeError::OutOfRange { offset, code_point } => {
+                write!(f, "{offset}: Code point {code_point:X} is out of range")
+            }
+            EscapeError::Surrogate { offset, code_point } => {
+                write!(f, "{offset}: Code point {code_point:X} is a surrogate")
+            }
+            EscapeError::NotOneChar { offset } => {
+                write!(f, "{offset}: Char literals must hold one character")
+            }
         }
-    } else if input[0] == b'{' {
-        let start_str_addr = input.as_ptr() as usize;
+    }
+}
+
+/// Decodes the `{...}` of a `\u{...}` escape starting at `offset` in the
+/// code, returning the character and the length of the braces.
+fn unicode_escape(input: &str, offset: usize) -> Result<(char, usize), EscapeError> {
+    let malformed = EscapeError::MalformedUnicode { offset };
 
-        while !input.is_empty() && input[0] != b'}' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+    let digits = input
+        .strip_prefix('{')
+        .and_then(|rest| rest.split_once('}'))
+        .map(|(digits, _)| digits)
+        .ok_or(malformed.clone())?;
+    if digits.is_empty() || digits.len() > 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(malformed);
+    }
+
+    let code_point = u32::from_str_radix(digits, 16).unwrap();
+    let value = char::from_u32(code_point).ok_or(match code_point {
+        0xD800..=0xDFFF => EscapeError::Surrogate { offset, code_point },
+        _ => EscapeError::OutOfRange { offset, code_point },
+    })?;
+    Ok((value, digits.len() + 2))
+}
+
+/// Words lexed as keywords by [`try_lex_with_keywords`], replacing the
+/// built-in keywords. The default set is the built-in one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeywordSet {
+    words: HashMap<String, TokenType>,
+}
+
+impl KeywordSet {
+    /// A set without any keywords, where every word is an identifier.
+    pub fn empty() -> Self {
+        Self {
+            words: HashMap::new(),
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed brace");
+    }
+
+    /// Makes `word` a keyword of the given type, returning the type it had.
+    pub fn insert(&mut self, word: &str, ty: TokenType) -> Option<TokenType> {
+        self.words.insert(word.to_string(), ty)
+    }
+
+    /// Makes `word` an identifier, returning the type it had.
+    pub fn remove(&mut self, word: &str) -> Option<TokenType> {
+        self.words.remove(word)
+    }
+
+    pub fn get(&self, word: &str) -> Option<TokenType> {
+        self.words.get(word).copied()
+    }
+}
+
+impl Default for KeywordSet {
+    fn default() -> Self {
+        let mut set = Self::empty();
+        for &(word, ty) in kw::ALL {
+            set.words
+                .insert(String::from_utf8(word.to_vec()).unwrap(), ty);
         }
+        set
     }
+}
 
-    let start_str_addr = input.as_ptr() as usize;
-    let col = start_str_addr + 1 - *line_start;
-    panic!("{file_name}:{line}:{col}: Cannot parse token");
+#[derive(Debug, Clone, Copy)]
+pub struct LexerConfig {
+    /// Whether keywords are recognized regardless of case, making `IF`, `If`
+    /// and `if` all [`TokenType::If`]. Token text is kept as written.
+    pub case_insensitive_keywords: bool,
+    /// Whether characters that don't start any token become
+    /// [`TokenType::Error`] tokens instead of a [`LexError::CannotParseToken`],
+    /// so the token stream covers the whole code.
+    pub recover_errors: bool,
+    /// Columns are counted in bytes, except that a tab advances to the next
+    /// multiple of `tab_width`, like tab stops in an editor.
+    pub tab_width: usize,
+    /// Whether plain `"` strings are interpolated like `$"` strings, so
+    /// `"a {x} b"` is lexed as `$"a {x} b"`. In them, `{{` is a literal brace
+    /// instead of the start of an expression part.
+    pub interpolate_plain_strings: bool,
+    /// Starts a comment running to the end of the line. Empty to have none.
+    pub line_comment: &'static str,
+    /// Start and end of comments that can span several lines. They don't nest.
+    pub block_comment: Option<(&'static str, &'static str)>,
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        Self {
+            case_insensitive_keywords: false,
+            recover_errors: false,
+            tab_width: 1,
+            interpolate_plain_strings: false,
+            line_comment: "//",
+            block_comment: Some(("/*", "*/")),
+        }
+    }
+}
+
+/// Lexer state carried between calls to [`next_token`].
+#[derive(Debug)]
+pub struct LexState {
+    pub config: LexerConfig,
+    /// Byte offset of the next token in the code
+    pub offset: usize,
+    /// Line of the next token, starting at 1
+    pub line: usize,
+    /// Byte offset of the first byte of the current line
+    pub line_start: usize,
+    /// Columns that tabs before the next token on its line take up in
+    /// addition to their one byte
+    tab_cols: usize,
+    /// Interpolated strings whose expression part we are in, innermost last
+    interps: Vec<Interp>,
+    /// Input given to [`lex_chunk`] that isn't lexed yet, since the token
+    /// at its start may continue in the next chunk
+    pending: String,
+    /// Whether offset 0 is the start of the file rather than of a fragment
+    /// of it, so a shebang line there is skipped
+    pub file_start: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Interp {
+    /// Braces opened and not yet closed inside the expression part
+    depth: usize,
+    /// Position of the string segment preceding the expression part
+    line: usize,
+    col: usize,
+    /// Whether `{{` is a literal brace in the string, see
+    /// [`LexerConfig::interpolate_plain_strings`]
+    brace_escapes: bool,
+}
+
+impl LexState {
+    pub fn new() -> Self {
+        Self::with_config(LexerConfig::default())
+    }
+
+    pub fn with_config(config: LexerConfig) -> Self {
+        Self {
+            config,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+            tab_cols: 0,
+            interps: Vec::new(),
+            pending: String::new(),
+            file_start: true,
+        }
+    }
+
+    /// Column of the next token as shown in error messages.
+    #[inline]
+    fn col(&self) -> usize {
+        self.token_col() + 1
+    }
+
+    /// Column of the next token as stored in its [`TokenSpan`].
+    #[inline]
+    fn token_col(&self) -> usize {
+        self.offset - self.line_start + self.tab_cols
+    }
+
+    /// Moves past `bytes`, the text of the token at [`LexState::offset`],
+    /// calling `on_line_break` with the offset of every line break in it.
+    fn advance(&mut self, bytes: &[u8], mut on_line_break: impl FnMut(usize)) {
+        let start = self.offset;
+
+        // whitespace, strings and chars can contain line breaks
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == b'\n' {
+                let offset = start + i;
+                on_line_break(offset);
+                self.line_start = offset + 1;
+                self.line += 1;
+                self.tab_cols = 0;
+            } else if byte == b'\t' && self.config.tab_width > 1 {
+                let tab_width = self.config.tab_width;
+                let col = start + i - self.line_start + self.tab_cols;
+                self.tab_cols += tab_width - 1 - col % tab_width;
+            }
+        }
+
+        self.offset = start + bytes.len();
+    }
+}
+
+impl Default for LexState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScannedToken {
+    /// `None` for whitespace and comments, which are not emitted
+    pub ty: Option<TokenType>,
+    /// Length of the token in bytes
+    pub len: usize,
+}
+
+pub fn lex<'a>(file_name: &str, code: &'a str) -> Tokens<'a> {
+    lex_with_config(file_name, code, LexerConfig::default())
+}
+
+pub fn lex_with_config<'a>(file_name: &str, code: &'a str, config: LexerConfig) -> Tokens<'a> {
+    match try_lex_with_config(code, config) {
+        Ok(tokens) => tokens,
+        Err(err) => panic!(
+            "{file_name}:{err}\n{}",
+            error_pointer(code, &err, config.tab_width)
+        ),
+    }
+}
+
+/// The line an error is on, followed by a line with a caret under the
+/// error's column.
+fn error_pointer(code: &str, err: &LexError, tab_width: usize) -> String {
+    let (line, col) = err.position();
+    let text = strip_bom(code).lines().nth(line - 1).unwrap_or_default();
+
+    // copy tabs, so the caret lines up however wide they are displayed
+    let mut pointer = String::new();
+    let mut width = 0;
+    for ch in text.chars() {
+        if width >= col - 1 {
+            break;
+        }
+        if ch == '\t' {
+            pointer.push('\t');
+            width += tab_width.max(1) - width % tab_width.max(1);
+        } else {
+            // columns count bytes, but the caret is for people reading it
+            pointer.push(' ');
+            width += ch.len_utf8();
+        }
+    }
+    pointer.push('^');
+
+    format!("{text}\n{pointer}")
+}
+
+/// Like [`lex`], but returns the error instead of panicking.
+pub fn try_lex(code: &str) -> Result<Tokens<'_>, LexError> {
+    try_lex_with_config(code, LexerConfig::default())
+}
+
+/// Like [`try_lex`], but for code that hasn't been checked to be UTF-8 yet.
+///
+/// Lexing stops at the first invalid byte, which is reported as
+/// [`LexError::InvalidUtf8`]. The tokens lexed before an error are returned
+/// along with it.
+pub fn try_lex_bytes(code: &[u8]) -> (Tokens<'_>, Option<LexError>) {
+    lex_utf8_prefix(code, LexerConfig::default())
+}
+
+/// Like [`try_lex_bytes`], but with a config.
+///
+/// With [`LexerConfig::recover_errors`], lexing continues past invalid
+/// bytes instead, and each invalid byte sequence becomes a
+/// [`TokenType::Error`] token. Since tokens can only refer to valid UTF-8,
+/// the code is then copied into `buf` with the invalid sequences replaced by
+/// U+FFFD, and token offsets are relative to `buf`.
+pub fn try_lex_bytes_with_config<'a>(
+    code: &'a [u8],
+    config: LexerConfig,
+    buf: &'a mut String,
+) -> (Tokens<'a>, Option<LexError>) {
+    if !config.recover_errors {
+        return lex_utf8_prefix(code, config);
+    }
+
+    let code = code.strip_prefix(BOM.as_bytes()).unwrap_or(code);
+    let code = match std::str::from_utf8(code) {
+        Ok(code) => code,
+        Err(_) => {
+            *buf = String::from_utf8_lossy(code).into_owned();
+            buf
+        }
+    };
+
+    let mut tokens = Tokens::new(code);
+    tokens.tab_width = config.tab_width;
+    let err = lex_into(&mut tokens, LexState::with_config(config), None).err();
+    (tokens, err)
+}
+
+/// Lexes `code` up to its first invalid byte.
+fn lex_utf8_prefix(code: &[u8], config: LexerConfig) -> (Tokens<'_>, Option<LexError>) {
+    let code = code.strip_prefix(BOM.as_bytes()).unwrap_or(code);
+    let valid_len = match std::str::from_utf8(code) {
+        Ok(_) => code.len(),
+        Err(err) => err.valid_up_to(),
+    };
+    // SAFETY: `from_utf8` checked the bytes up to `valid_len`
+    let valid = unsafe { std::str::from_utf8_unchecked(&code[..valid_len]) };
+
+    let mut tokens = Tokens::new(valid);
+    tokens.tab_width = config.tab_width;
+    if let Err(err) = lex_into(&mut tokens, LexState::with_config(config), None) {
+        return (tokens, Some(err));
+    }
+    if valid_len == code.len() {
+        return (tokens, None);
+    }
+
+    let line_start = valid.rfind('\n').map_or(0, |idx| idx + 1);
+    let err = LexError::InvalidUtf8 {
+        line: tokens.line_breaks.len() + 1,
+        col: valid_len - line_start + 1,
+    };
+    (tokens, Some(err))
+}
+
+pub fn try_lex_with_config(code: &str, config: LexerConfig) -> Result<Tokens<'_>, LexError> {
+    lex_impl(code, config, None)
+}
+
+/// Lexes a fragment of a larger file, like a macro argument, that starts at
+/// `base_line` and `base_col` and at byte `base_offset` of the file.
+///
+/// Spans stay relative to the fragment, so they index into
+/// [`Tokens::code`] like usual; [`Tokens::file_position`] gives a token's
+/// position in the file. Error positions are those in the file, where
+/// `base_col` only affects the fragment's first line.
+///
+/// # Panics
+///
+/// If `base_line` is 0, since lines start at 1.
+pub fn try_lex_at_offset(
+    code: &str,
+    base_line: usize,
+    base_col: usize,
+    base_offset: usize,
+) -> Result<Tokens<'_>, LexError> {
+    assert!(base_line > 0, "Lines start at 1");
+    let mut tokens = Tokens::new(strip_bom(code));
+    let mut state = LexState::new();
+    state.file_start = base_offset == 0;
+    lex_into(&mut tokens, state, None).map_err(|err| err.shifted(base_line, base_col))?;
+    tokens.base_offset = base_offset;
+    tokens.base_line = base_line;
+    tokens.base_col = base_col;
+    Ok(tokens)
+}
+
+/// Like [`try_lex`], but with the keywords in `keywords` instead of the
+/// built-in ones. Words that aren't in the set are identifiers.
+pub fn try_lex_with_keywords<'a>(
+    code: &'a str,
+    keywords: &KeywordSet,
+) -> Result<Tokens<'a>, LexError> {
+    let mut tokens = try_lex(code)?;
+
+    // the built-in keywords are whole words, so every word is either one of
+    // them or an identifier
+    let Tokens { types, spans, .. } = &mut tokens;
+    for (ty, span) in types.iter_mut().zip(spans.iter()) {
+        if *ty == TokenType::Ident || kw::ALL.iter().any(|&(_, kw_ty)| kw_ty == *ty) {
+            *ty = keywords.get(span.slice).unwrap_or(TokenType::Ident);
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Some editors start UTF-8 files with the encoded U+FEFF as a byte order mark.
+/// It isn't part of the code, so the lexer skips it.
+const BOM: &str = "\u{FEFF}";
+
+fn strip_bom(code: &str) -> &str {
+    code.strip_prefix(BOM).unwrap_or(code)
+}
+
+/// How much input is lexed between calls to the progress callback of
+/// [`lex_with_progress`].
+const PROGRESS_INTERVAL: usize = MIB;
+
+/// Like [`lex`], but calls `progress` with the fraction of the code lexed so
+/// far after every [`PROGRESS_INTERVAL`] bytes, and with `1.0` once done.
+pub fn lex_with_progress<'a>(
+    file_name: &str,
+    code: &'a str,
+    progress: &mut dyn FnMut(f32),
+) -> Tokens<'a> {
+    match lex_impl(code, LexerConfig::default(), Some(progress)) {
+        Ok(tokens) => tokens,
+        Err(err) => panic!(
+            "{file_name}:{err}\n{}",
+            error_pointer(code, &err, LexerConfig::default().tab_width)
+        ),
+    }
+}
+
+fn lex_impl<'a>(
+    code: &'a str,
+    config: LexerConfig,
+    progress: Option<&mut dyn FnMut(f32)>,
+) -> Result<Tokens<'a>, LexError> {
+    let mut tokens = Tokens::new(strip_bom(code));
+    tokens.tab_width = config.tab_width;
+    lex_into(&mut tokens, LexState::with_config(config), progress)?;
+    Ok(tokens)
+}
+
+/// Lexes `tokens.code` into `tokens`, leaving the tokens lexed so far in
+/// place on errors.
+fn lex_into(
+    tokens: &mut Tokens,
+    mut state: LexState,
+    mut progress: Option<&mut dyn FnMut(f32)>,
+) -> Result<(), LexError> {
+    let code = tokens.code;
+    let bcode = code.as_bytes();
+    let mut next_progress = PROGRESS_INTERVAL;
+
+    while let Some(scanned) = next_token(&bcode[state.offset..], &mut state) {
+        let scanned = scanned?;
+
+        let start = state.offset;
+        let end = start + scanned.len;
+
+        if let Some(ty) = scanned.ty {
+            tokens.types.add(ty);
+            tokens.spans.add(TokenSpan::new(
+                &code[start..end],
+                start,
+                state.line,
+                state.token_col(),
+            ));
+        }
+
+        state.advance(&bcode[start..end], |offset| tokens.line_breaks.add(offset));
+
+        // the end is reported once lexing is done
+        if state.offset >= next_progress && state.offset < bcode.len() {
+            if let Some(progress) = &mut progress {
+                progress(state.offset as f32 / bcode.len() as f32);
+            }
+            next_progress = state.offset + PROGRESS_INTERVAL;
+        }
+    }
+
+    if let Some(progress) = &mut progress {
+        progress(1.0);
+    }
+
+    Ok(())
+}
+
+/// A token lexed by [`lex_chunk`]. It owns its text, since the chunks it
+/// was lexed from may be gone by now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkToken {
+    pub ty: TokenType,
+    pub text: String,
+    /// Like in [`TokenSpan`], relative to the start of the first chunk
+    pub offset: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// How many bytes past the end of a token the lexer may look at to decide
+/// where the token ends, like the `.8` it checks for after `0x1`, or a comment
+/// delimiter that starts with a token like `<!--` does with `<`.
+fn chunk_lookahead(config: &LexerConfig) -> usize {
+    let (start, end) = config.block_comment.unwrap_or_default();
+    [config.line_comment, start, end]
+        .iter()
+        .map(|delimiter| delimiter.len())
+        .fold(2, usize::max)
+}
+
+/// Lexes the next piece of code read so far, continuing where the previous
+/// chunk lexed with `state` left off. Tokens are only returned once it's
+/// certain that later chunks can't change them, so a token split across two
+/// chunks is returned with the second one. Call [`try_lex_finish`] after the
+/// last chunk for the tokens at the very end.
+///
+/// Errors are only reported by [`try_lex_finish`], since until then the
+/// input might still continue in a way that makes them go away, like the
+/// closing quote of an unfinished string.
+pub fn lex_chunk(state: &mut LexState, chunk: &str) -> Vec<ChunkToken> {
+    // like `lex`, skip a byte order mark at the start of the code
+    let chunk = if state.offset == 0 && state.pending.is_empty() {
+        strip_bom(chunk)
+    } else {
+        chunk
+    };
+    state.pending.push_str(chunk);
+    lex_pending(state, false).unwrap_or_default()
+}
+
+/// Lexes what is left after the last chunk given to [`lex_chunk`].
+pub fn try_lex_finish(state: &mut LexState) -> Result<Vec<ChunkToken>, LexError> {
+    lex_pending(state, true)
+}
+
+/// Lexes [`LexState::pending`]. Unless this is the `last` of the input, stops
+/// at the first token or error that more input could still change.
+fn lex_pending(state: &mut LexState, last: bool) -> Result<Vec<ChunkToken>, LexError> {
+    let pending = mem::take(&mut state.pending);
+    let bytes = pending.as_bytes();
+    let base = state.offset;
+    let lookahead = chunk_lookahead(&state.config);
+    let mut tokens = Vec::new();
+
+    loop {
+        let start = state.offset - base;
+        let interps = state.interps.clone();
+
+        let scanned = match next_token(&bytes[start..], state) {
+            None => break,
+            Some(Ok(scanned)) if last || start + scanned.len + lookahead <= bytes.len() => scanned,
+            Some(Err(err)) if last => return Err(err),
+            // wait for the next chunk, undoing what scanning changed
+            Some(_) => {
+                state.interps = interps;
+                state.pending = pending[start..].to_string();
+                return Ok(tokens);
+            }
+        };
+
+        let end = start + scanned.len;
+        if let Some(ty) = scanned.ty {
+            tokens.push(ChunkToken {
+                ty,
+                text: pending[start..end].to_string(),
+                offset: state.offset,
+                line: state.line,
+                col: state.token_col(),
+            });
+        }
+        state.advance(&bytes[start..end], |_| {});
+    }
+
+    Ok(tokens)
+}
+
+/// Scans the token at the start of `input`, which is the remaining code
+/// starting at `state.offset`.
+///
+/// Returns `None` once the input is exhausted. The caller is responsible for
+/// advancing `state.offset` past the token and for keeping the line
+/// information in `state` up to date, since tokens like strings may span
+/// several lines.
+pub fn next_token(input: &[u8], state: &mut LexState) -> Option<Result<ScannedToken, LexError>> {
+    if input.is_empty() {
+        return state.interps.last().map(|interp| {
+            Err(LexError::UnfinishedInterpString {
+                line: interp.line,
+                col: interp.col,
+            })
+        });
+    }
+
+    let scanned = scan_token(input, state).map(|(ty, rest)| ScannedToken {
+        ty,
+        len: input.len() - rest.len(),
+    });
+
+    Some(scanned)
+}
+
+/// Whether `input` starts with the comment `delimiter`. Delimiters ending in
+/// an identifier character, like `rem`, also have to end the word there, so
+/// `remark` is still an identifier.
+fn starts_with_delimiter(input: &[u8], delimiter: &str) -> bool {
+    let is_ident_byte = |byte: &u8| matches!(byte, b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9');
+    let delimiter = delimiter.as_bytes();
+
+    !delimiter.is_empty()
+        && input.starts_with(delimiter)
+        && !(delimiter.last().is_some_and(is_ident_byte)
+            && input.get(delimiter.len()).is_some_and(is_ident_byte))
+}
+
+/// Scans the token at the start of the non-empty `input`, returning its type
+/// and the input following it.
+fn scan_token<'a>(
+    mut input: &'a [u8],
+    state: &mut LexState,
+) -> Result<(Option<TokenType>, &'a [u8]), LexError> {
+    // ignore whitespace
+    if input[0].is_ascii_whitespace() {
+        while !input.is_empty() && input[0].is_ascii_whitespace() {
+            input = &input[1..];
+        }
+        return Ok((None, input));
+    }
+
+    // ignore a shebang line, but only at the very start of the file
+    if state.file_start && state.offset == 0 && input.starts_with(b"#!") {
+        while !input.is_empty() && input[0] != b'\n' {
+            input = &input[1..];
+        }
+        return Ok((None, input));
+    }
+
+    // ignore comments
+    // the line break ending a comment is left in the input and skipped as
+    // whitespace next, so the driver sees it and keeps counting lines
+    let line_comment = state.config.line_comment;
+    if starts_with_delimiter(input, line_comment) {
+        input = &input[line_comment.len()..];
+        while !input.is_empty() && input[0] != b'\n' {
+            input = &input[1..];
+        }
+        return Ok((None, input));
+    }
+    if let Some((start, end)) = state.config.block_comment {
+        if starts_with_delimiter(input, start) {
+            let col = state.col();
+
+            input = &input[start.len()..];
+            while !input.is_empty() {
+                if input.starts_with(end.as_bytes()) {
+                    return Ok((None, &input[end.len()..]));
+                }
+                input = &input[1..];
+            }
+
+            return Err(LexError::UnfinishedComment {
+                line: state.line,
+                col,
+            });
+        }
+    }
+
+    // braces inside the expression part of interpolated strings
+    if let Some(interp) = state.interps.last_mut() {
+        match input[0] {
+            b'{' => interp.depth += 1,
+            b'}' if interp.depth > 0 => interp.depth -= 1,
+            b'}' => return scan_interp_segment(input, state),
+            _ => {}
+        }
+    }
+
+    // operators
+    // dispatching on the first byte means only the few operators sharing it
+    // are compared against the rest of the input
+    let operator = match input[0] {
+        op::EQUAL if input.starts_with(op::EQUALS) => Some((TokenType::Equals, 2)),
+        op::EQUAL => Some((TokenType::Equal, 1)),
+        b'!' if input.starts_with(op::NOT_EQUALS) => Some((TokenType::NotEquals, 2)),
+        op::LESS_THAN if input.starts_with(op::LESS_EQUAL) => Some((TokenType::LessEqual, 2)),
+        op::LESS_THAN if input.starts_with(op::L_SHIFT) => Some((TokenType::LShift, 2)),
+        op::LESS_THAN => Some((TokenType::LessThan, 1)),
+        op::GREATER_THAN if input.starts_with(op::GREATER_EQUAL) => {
+            Some((TokenType::GreaterEqual, 2))
+        }
+        op::GREATER_THAN if input.starts_with(op::FEATHER) => Some((TokenType::Feather, 2)),
+        op::GREATER_THAN if input.starts_with(op::R_SHIFT) => Some((TokenType::RShift, 2)),
+        op::GREATER_THAN => Some((TokenType::GreaterThan, 1)),
+        op::MINUS if input.starts_with(op::ARROW) => Some((TokenType::Arrow, 2)),
+        op::MINUS if input.starts_with(op::DECR) => Some((TokenType::Decr, 2)),
+        op::MINUS => Some((TokenType::Minus, 1)),
+        op::PLUS if input.starts_with(op::INCR) => Some((TokenType::Incr, 2)),
+        op::PLUS => Some((TokenType::Plus, 1)),
+        op::MUL if input.starts_with(op::POW) => Some((TokenType::Pow, 2)),
+        op::MUL => Some((TokenType::Mul, 1)),
+        op::MODULO => Some((TokenType::Modulo, 1)),
+        op::AMPERSAND => Some((TokenType::Ampersand, 1)),
+        op::PIPE => Some((TokenType::Pipe, 1)),
+        op::CARET => Some((TokenType::Caret, 1)),
+        op::TILDE => Some((TokenType::Tilde, 1)),
+        op::DIV => Some((TokenType::Div, 1)),
+        op::SEMI => Some((TokenType::Semi, 1)),
+        op::COLON => Some((TokenType::Colon, 1)),
+        op::COMMA => Some((TokenType::Comma, 1)),
+        op::DOT => Some((TokenType::Dot, 1)),
+        op::L_PARENS => Some((TokenType::LParens, 1)),
+        op::R_PARENS => Some((TokenType::RParens, 1)),
+        op::L_BRACKET => Some((TokenType::LBracket, 1)),
+        op::R_BRACKET => Some((TokenType::RBracket, 1)),
+        op::L_BRACE => Some((TokenType::LBrace, 1)),
+        op::R_BRACE => Some((TokenType::RBrace, 1)),
+        op::AT => Some((TokenType::At, 1)),
+        op::QUESTION => Some((TokenType::Question, 1)),
+        _ => None,
+    };
+
+    if let Some((toktype, op_len)) = operator {
+        return Ok((Some(toktype), &input[op_len..]));
+    }
+
+    // interpolated strings
+    if input.starts_with(b"$\"") {
+        return scan_interp_segment(input, state);
+    }
+
+    // block strings
+    if input.starts_with(b"\"\"\"") {
+        let col = state.col();
+
+        input = &input[3..];
+        while !input.is_empty() {
+            if input.starts_with(b"\"\"\"") {
+                return Ok((Some(TokenType::String), &input[3..]));
+            }
+            input = &input[1..];
+        }
+
+        return Err(LexError::UnfinishedString {
+            line: state.line,
+            col,
+        });
+    }
+
+    if state.config.interpolate_plain_strings && input[0] == b'"' {
+        return scan_interp_segment(input, state);
+    }
+
+    // strings
+    // todo: raw strings (like in Rust)
+    let (is_string, prefix): (bool, &[u8]) = if input.starts_with(b"b\"") {
+        (true, b"b\"")
+    } else if input.starts_with(b"c\"") {
+        (true, b"c\"")
+    } else if input[0] == b'"' {
+        (true, b"\"")
+    } else {
+        (false, b"")
+    };
+
+    if is_string {
+        let col = state.col();
+
+        input = &input[prefix.len()..];
+        while !input.is_empty() {
+            if input.starts_with(br#"\""#) {
+                input = &input[2..];
+                continue;
+            }
+
+            if input[0] == b'"' {
+                return Ok((Some(TokenType::String), &input[1..]));
+            }
+
+            // strings support line breaks
+            input = &input[1..];
+        }
+
+        return Err(LexError::UnfinishedString {
+            line: state.line,
+            col,
+        });
+    }
+
+    // chars
+    let (is_char, prefix): (bool, &[u8]) = if input.starts_with(b"b'") {
+        (true, b"b'")
+    } else if input[0] == b'\'' {
+        (true, b"'")
+    } else {
+        (false, b"")
+    };
+
+    if is_char {
+        let col = state.col();
+
+        input = &input[prefix.len()..];
+        while !input.is_empty() {
+            if input.starts_with(br#"\'"#) {
+                input = &input[2..];
+                continue;
+            }
+
+            if input[0] == b'\'' {
+                return Ok((Some(TokenType::Char), &input[1..]));
+            }
+
+            // chars can handle line breaks (though they shouldn't be allowed)
+            input = &input[1..];
+        }
+
+        return Err(LexError::UnfinishedChar {
+            line: state.line,
+            col,
+        });
+    }
+
+    // identifiers
+    if matches!(input[0], b'_' | b'A'..=b'Z' | b'a'..=b'z') {
+        let ident_start = input;
+
+        input = &input[1..];
+        while matches!(
+            input.first(),
+            Some(b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9')
+        ) {
+            input = &input[1..];
+        }
+
+        let ident_slice = &ident_start[..ident_start.len() - input.len()];
+
+        // keywords are at most 8 bytes long, so longer identifiers don't need
+        // lowercasing
+        let mut lowered = [0; 8];
+        let ident_slice =
+            if state.config.case_insensitive_keywords && ident_slice.len() <= lowered.len() {
+                let len = ident_slice.len();
+                lowered[..len].copy_from_slice(ident_slice);
+                lowered[..len].make_ascii_lowercase();
+                &lowered[..len]
+            } else {
+                ident_slice
+            };
+
+        let toktype = 'kw: {
+            // a lone underscore is a wildcard, not a name
+            if ident_slice == b"_" {
+                break 'kw TokenType::Underscore;
+            }
+
+            // keywords
+
+            let token_len = 8;
+            if ident_slice.len() == token_len {
+                let toktype = if &ident_slice[..token_len] == kw::CONTINUE {
+                    Some(TokenType::Continue)
+                } else {
+                    None
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            let token_len = 6;
+            if ident_slice.len() == token_len {
+                let toktype = match &ident_slice[..token_len] {
+                    kw::PACKED => Some(TokenType::Packed),
+                    kw::STRUCT => Some(TokenType::Struct),
+                    _ => None,
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            let token_len = 5;
+            if ident_slice.len() == token_len {
+                let toktype = match &ident_slice[..token_len] {
+                    kw::UNION => Some(TokenType::Union),
+                    kw::DEFER => Some(TokenType::Defer),
+                    kw::WHILE => Some(TokenType::While),
+                    kw::BREAK => Some(TokenType::Break),
+                    _ => None,
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            let token_len = 4;
+            if ident_slice.len() == token_len {
+                let toktype = match &ident_slice[..token_len] {
+                    kw::ENUM => Some(TokenType::Enum),
+                    kw::THEN => Some(TokenType::Then),
+                    kw::ELSE => Some(TokenType::Else),
+                    kw::LOOP => Some(TokenType::Loop),
+                    _ => None,
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            let token_len = 3;
+            if ident_slice.len() == token_len {
+                let toktype = match &ident_slice[..token_len] {
+                    kw::AND => Some(TokenType::And),
+                    kw::XOR => Some(TokenType::Xor),
+                    kw::NOT => Some(TokenType::Not),
+                    kw::PUB => Some(TokenType::Pub),
+                    _ => None,
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            let token_len = 2;
+            if ident_slice.len() == token_len {
+                let toktype = match &ident_slice[..token_len] {
+                    kw::OR => Some(TokenType::Or),
+                    kw::FN => Some(TokenType::Fn),
+                    kw::IF => Some(TokenType::If),
+                    kw::DO => Some(TokenType::Do),
+                    _ => None,
+                };
+
+                if let Some(toktype) = toktype {
+                    break 'kw toktype;
+                }
+            }
+
+            TokenType::Ident
+        };
+
+        return Ok((Some(toktype), input));
+    }
+
+    // numbers
+    if input[0].is_ascii_digit() {
+        let num_start = input;
+
+        if input.starts_with(b"0x") {
+            // hex literals
+            fn hex_digits(mut input: &[u8]) -> &[u8] {
+                while matches!(
+                    input.first(),
+                    Some(b'_' | b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F')
+                ) {
+                    input = &input[1..];
+                }
+                input
+            }
+            input = hex_digits(&input[2..]);
+
+            // hex floats, like `0x1.8p3`, which need a binary exponent
+            let has_fraction =
+                input.first() == Some(&b'.') && input.get(1).is_some_and(u8::is_ascii_hexdigit);
+            if has_fraction {
+                input = hex_digits(&input[1..]);
+            }
+            if matches!(input.first(), Some(b'p' | b'P')) {
+                input = &input[1..];
+                if matches!(input.first(), Some(b'+' | b'-')) {
+                    input = &input[1..];
+                }
+                if !input.first().is_some_and(u8::is_ascii_digit) {
+                    if state.config.recover_errors {
+                        return Ok((Some(TokenType::Error), input));
+                    }
+
+                    let num_len = num_start.len() - input.len();
+                    return Err(LexError::CannotParseToken {
+                        line: state.line,
+                        col: state.col() + num_len,
+                    });
+                }
+                while matches!(input.first(), Some(b'_' | b'0'..=b'9')) {
+                    input = &input[1..];
+                }
+            } else if has_fraction {
+                if state.config.recover_errors {
+                    return Ok((Some(TokenType::Error), input));
+                }
+
+                let num_len = num_start.len() - input.len();
+                return Err(LexError::MissingHexExponent {
+                    line: state.line,
+                    col: state.col() + num_len,
+                });
+            }
+        } else if input.starts_with(b"0o") {
+            // octal literals
+            input = &input[2..];
+            while matches!(input.first(), Some(b'_' | b'0'..=b'7')) {
+                input = &input[1..];
+            }
+        } else if input.starts_with(b"0b") {
+            // binary literals
+            input = &input[2..];
+            while matches!(input.first(), Some(b'_' | b'0'..=b'1')) {
+                input = &input[1..];
+            }
+        } else {
+            // decimal and floating literals
+
+            // whole part
+            input = &input[1..];
+            while matches!(input.first(), Some(b'_' | b'0'..=b'9')) {
+                input = &input[1..];
+            }
+
+            // fractional part
+            if input.first() == Some(&b'.') {
+                input = &input[1..];
+                while matches!(input.first(), Some(b'_' | b'0'..=b'9')) {
+                    input = &input[1..];
+                }
+            }
+
+            // exponent
+            if matches!(input.first(), Some(b'e' | b'E')) {
+                input = &input[1..];
+                if matches!(input.first(), Some(b'+' | b'-')) {
+                    input = &input[1..];
+                }
+                while matches!(input.first(), Some(b'_' | b'0'..=b'9')) {
+                    input = &input[1..];
+                }
+            }
+        }
+
+        // type suffix, like `255u8`
+        let suffix_len = input
+            .iter()
+            .take_while(|byte| matches!(byte, b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9'))
+            .count();
+        if suffix_len > 0 {
+            if NumSuffix::from_bytes(&input[..suffix_len]).is_none() {
+                if state.config.recover_errors {
+                    return Ok((Some(TokenType::Error), &input[suffix_len..]));
+                }
+
+                let num_len = num_start.len() - input.len();
+                return Err(LexError::InvalidNumSuffix {
+                    line: state.line,
+                    col: state.col() + num_len,
+                });
+            }
+            input = &input[suffix_len..];
+        }
+
+        return Ok((Some(TokenType::Num), input));
+    }
+
+    if state.config.recover_errors {
+        // skip the whole UTF-8 character so the token text stays valid
+        let char_len = match input[0] {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1,
+        };
+        return Ok((Some(TokenType::Error), &input[char_len.min(input.len())..]));
+    }
+
+    // whitespace was skipped above
+    if input[0] < 0x20 {
+        return Err(LexError::ControlChar {
+            byte: input[0],
+            line: state.line,
+            col: state.col(),
+        });
+    }
+
+    Err(LexError::CannotParseToken {
+        line: state.line,
+        col: state.col(),
+    })
+}
+
+/// Scans a segment of an interpolated string, starting either at its `$"` (or
+/// `"`) or at the `}` ending an expression part, and ending either at the
+/// closing `"` or at the `{` starting the next expression part.
+fn scan_interp_segment<'a>(
+    mut input: &'a [u8],
+    state: &mut LexState,
+) -> Result<(Option<TokenType>, &'a [u8]), LexError> {
+    let line = state.line;
+    let col = state.col();
+
+    let has_interpolation = input[0] == b'}';
+    let brace_escapes = match input[0] {
+        b'}' => state.interps.pop().unwrap().brace_escapes,
+        b'"' => true,
+        _ => false,
+    };
+    input = match input[0] {
+        b'$' => &input[2..],
+        _ => &input[1..],
+    };
+
+    while !input.is_empty() {
+        if input.starts_with(br#"\""#) || input.starts_with(br#"\{"#) {
+            input = &input[2..];
+            continue;
+        }
+        if brace_escapes && input.starts_with(b"{{") {
+            input = &input[2..];
+            continue;
+        }
+
+        if input[0] == b'"' {
+            // end of string
+
+            let toktype = match has_interpolation {
+                true => TokenType::StringInterpEnd,
+                false => TokenType::String,
+            };
+            return Ok((Some(toktype), &input[1..]));
+        } else if input[0] == b'{' {
+            // start of an interpolated expression, whose tokens are scanned
+            // normally until its closing brace
+
+            state.interps.push(Interp {
+                depth: 0,
+                line,
+                col,
+                brace_escapes,
+            });
+
+            let toktype = match has_interpolation {
+                true => TokenType::StringInterpMid,
+                false => TokenType::StringInterpBeg,
+            };
+            return Ok((Some(toktype), &input[1..]));
+        }
+
+        // strings support line breaks
+        input = &input[1..];
+    }
+
+    Err(LexError::UnfinishedInterpString { line, col })
+}
+
+#[cfg(test)]
+mod tests_lex_chunk {
+    use crate::lexer::{
+        lex, lex_chunk, lex_with_config, try_lex_finish, ChunkToken, LexError, LexState,
+        LexerConfig, Tokens,
+    };
+
+    fn chunk_tokens(tokens: &Tokens) -> Vec<ChunkToken> {
+        tokens
+            .types
+            .iter()
+            .zip(tokens.spans.iter())
+            .map(|(&ty, span)| ChunkToken {
+                ty,
+                text: span.slice.to_string(),
+                offset: span.offset,
+                line: span.line,
+                col: span.col,
+            })
+            .collect()
+    }
+
+    fn lex_in_chunks(chunks: &[&str]) -> Result<Vec<ChunkToken>, LexError> {
+        let mut state = LexState::new();
+        let mut tokens = Vec::new();
+        for chunk in chunks {
+            tokens.extend(lex_chunk(&mut state, chunk));
+        }
+        tokens.extend(try_lex_finish(&mut state)?);
+        Ok(tokens)
+    }
+
+    #[test]
+    fn two_halves() {
+        let code = include_str!("../tests/corpus/misc.sus");
+        let expected = chunk_tokens(&lex("misc.sus", code));
+
+        let mut mid = code.len() / 2;
+        while !code.is_char_boundary(mid) {
+            mid += 1;
+        }
+        let (a, b) = code.split_at(mid);
+        assert_eq!(lex_in_chunks(&[a, b]).unwrap(), expected);
+    }
+
+    #[test]
+    fn every_split() {
+        let code = "main :: fn() {\n\tx := $\"a {b + 0x1.8p3} c\" <= 'ඞ'; // done\n}";
+        let expected = chunk_tokens(&lex("test", code));
+
+        for mid in (0..=code.len()).filter(|&mid| code.is_char_boundary(mid)) {
+            let (a, b) = code.split_at(mid);
+            assert_eq!(lex_in_chunks(&[a, b]).unwrap(), expected, "split at {mid}");
+        }
+    }
+
+    #[test]
+    fn split_comment_delimiter() {
+        let config = LexerConfig {
+            block_comment: Some(("<!--", "-->")),
+            ..LexerConfig::default()
+        };
+        let code = "a <!-- hi --> b";
+        let expected = chunk_tokens(&lex_with_config("test", code, config));
+        assert_eq!(expected.len(), 2);
+
+        for mid in 0..=code.len() {
+            let (a, b) = code.split_at(mid);
+            let mut state = LexState::with_config(config);
+            let mut tokens = lex_chunk(&mut state, a);
+            tokens.extend(lex_chunk(&mut state, b));
+            tokens.extend(try_lex_finish(&mut state).unwrap());
+            assert_eq!(tokens, expected, "split at {mid}");
+        }
+    }
+
+    #[test]
+    fn bom() {
+        let code = "\u{FEFF}x := 1;";
+        let expected = chunk_tokens(&lex("test", code));
+
+        for mid in (0..=code.len()).filter(|&mid| code.is_char_boundary(mid)) {
+            let (a, b) = code.split_at(mid);
+            assert_eq!(lex_in_chunks(&[a, b]).unwrap(), expected, "split at {mid}");
+        }
+    }
+
+    #[test]
+    fn tokens_before_split_come_first() {
+        let mut state = LexState::new();
+        let tokens = lex_chunk(&mut state, "foo bar; \"unfin");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, ["foo", "bar", ";"]);
+
+        let tokens = lex_chunk(&mut state, "ished\" x");
+        assert_eq!(tokens[0].text, "\"unfinished\"");
+        assert_eq!((tokens[0].offset, tokens[0].col), (9, 9));
+        assert_eq!(try_lex_finish(&mut state).unwrap()[0].text, "x");
+    }
+
+    #[test]
+    fn error_at_finish() {
+        assert_eq!(
+            lex_in_chunks(&["a;\n", "\"b"]),
+            Err(LexError::UnfinishedString { line: 2, col: 1 })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_next_token {
+    use crate::lexer::lex;
+
+    #[test]
+    fn matches_snapshots() {
+        let tokens = lex("Cඞඞ.sus", include_str!("../Cඞඞ.sus"));
+        assert_eq!(
+            tokens.to_string(),
+            include_str!("../tests/snapshots/Cඞඞ.tokens")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_operators {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn every_operator() {
+        let operators = [
+            ("==", TokenType::Equals),
+            ("!=", TokenType::NotEquals),
+            ("<", TokenType::LessThan),
+            (">", TokenType::GreaterThan),
+            ("<=", TokenType::LessEqual),
+            (">=", TokenType::GreaterEqual),
+            (">-", TokenType::Feather),
+            ("->", TokenType::Arrow),
+            ("&", TokenType::Ampersand),
+            ("|", TokenType::Pipe),
+            ("^", TokenType::Caret),
+            ("~", TokenType::Tilde),
+            ("<<", TokenType::LShift),
+            (">>", TokenType::RShift),
+            ("++", TokenType::Incr),
+            ("--", TokenType::Decr),
+            ("+", TokenType::Plus),
+            ("-", TokenType::Minus),
+            ("*", TokenType::Mul),
+            ("/", TokenType::Div),
+            ("**", TokenType::Pow),
+            ("%", TokenType::Modulo),
+            ("=", TokenType::Equal),
+            (";", TokenType::Semi),
+            (":", TokenType::Colon),
+            (",", TokenType::Comma),
+            (".", TokenType::Dot),
+            ("(", TokenType::LParens),
+            (")", TokenType::RParens),
+            ("[", TokenType::LBracket),
+            ("]", TokenType::RBracket),
+            ("{", TokenType::LBrace),
+            ("}", TokenType::RBrace),
+            ("@", TokenType::At),
+            ("?", TokenType::Question),
+        ];
+
+        // alone, and followed by a byte that can't extend them
+        for (op, ty) in operators {
+            for code in [op.to_string(), format!("{op} ")] {
+                let tokens = lex("test", &code);
+                assert_eq!(tokens.types.as_slice(), &[ty], "{code:?}");
+                assert_eq!(tokens.spans[0].slice, op);
+            }
+        }
+    }
+
+    #[test]
+    fn max_munch() {
+        let tokens = lex("test", "<<=>>=**-->");
+        assert_eq!(
+            tokens.types.as_slice(),
+            &[
+                TokenType::LShift,
+                TokenType::Equal,
+                TokenType::RShift,
+                TokenType::Equal,
+                TokenType::Pow,
+                TokenType::Decr,
+                TokenType::GreaterThan,
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_offsets {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn multiline_string() {
+        let tokens = lex("test", "a \"x\ny\" b\n  c;");
+        assert_eq!(
+            tokens.types.as_slice(),
+            &[
+                TokenType::Ident,
+                TokenType::String,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Semi,
+            ]
+        );
+
+        let positions: Vec<_> = tokens.spans.iter().map(|s| (s.line, s.col)).collect();
+        assert_eq!(positions, [(1, 0), (1, 2), (2, 3), (3, 2), (3, 3)]);
+        assert_eq!(tokens.spans[1].slice, "\"x\ny\"");
+        assert_eq!(tokens.line_breaks.as_slice(), &[4, 9]);
+    }
+}
+
+#[cfg(test)]
+mod tests_positions {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn two_fns() {
+        let tokens = lex("test", "add :: fn() {}\nsub :: fn() {}");
+        assert!(tokens.contains_type(TokenType::Fn));
+        assert!(!tokens.contains_type(TokenType::Struct));
+        assert_eq!(tokens.positions(TokenType::Fn).collect::<Vec<_>>(), [3, 11]);
+    }
+}
+
+#[cfg(test)]
+mod tests_merge_span {
+    use crate::lexer::lex;
+
+    #[test]
+    fn merge() {
+        let tokens = lex("test", "a + b;");
+        assert_eq!(tokens.merge_span(0..3), "a + b");
+        assert_eq!(tokens.merge_span(1..3), "+ b");
+        assert_eq!(tokens.merge_span(1..2), "+");
+        assert_eq!(tokens.merge_span(2..2), "");
+
+        let tokens = lex("test", "f(a, // first\n  b)");
+        assert_eq!(tokens.merge_span(2..6), "a, // first\n  b)");
+    }
+}
+
+#[cfg(test)]
+mod tests_token_groups {
+    use crate::lexer::TokenType;
+
+    #[test]
+    fn comparison() {
+        assert!(TokenType::LessEqual.is_comparison());
+        assert!(TokenType::NotEquals.is_comparison());
+        assert!(!TokenType::Equal.is_comparison());
+        assert!(!TokenType::Plus.is_comparison());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert!(TokenType::Pow.is_arithmetic());
+        assert!(TokenType::Incr.is_arithmetic());
+        assert!(!TokenType::Ampersand.is_arithmetic());
+        assert!(!TokenType::Equals.is_arithmetic());
+    }
+}
+
+#[cfg(test)]
+mod tests_last {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn empty() {
+        let tokens = lex("test", "");
+        assert!(tokens.is_empty());
+        assert_eq!(tokens.last(), None);
+
+        let tokens = lex("test", "  // nothing here\n");
+        assert!(tokens.is_empty());
+        assert_eq!(tokens.last(), None);
+    }
+
+    #[test]
+    fn non_empty() {
+        let tokens = lex("test", "a + b;\n  c[0]\n// end\n");
+        assert!(!tokens.is_empty());
+        assert_eq!(tokens.last(), Some((TokenType::RBracket, "]", 2, 5)));
+    }
+}
+
+#[cfg(test)]
+mod tests_iter_with_line {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn line_text() {
+        let tokens = lex("test", "a + b;\r\n\n  f(\"x\ny\")\n");
+        let rows: Vec<_> = tokens.iter_with_line().collect();
+        assert_eq!(rows.len(), tokens.types.len());
+
+        let texts: Vec<&str> = rows.iter().map(|&(_, _, text)| text).collect();
+        assert_eq!(
+            texts,
+            ["a + b;", "a + b;", "a + b;", "a + b;", "  f(\"x", "  f(\"x", "  f(\"x", "y\")"]
+        );
+        assert_eq!(rows[6].0, TokenType::String);
+
+        // tokens on the same line share the same slice of the code
+        assert_eq!(rows[0].2.as_ptr(), rows[3].2.as_ptr());
+        assert_eq!(rows[0].2.as_ptr(), tokens.code.as_ptr());
+    }
+}
+
+#[cfg(test)]
+mod tests_case_insensitive {
+    use crate::lexer::{lex, lex_with_config, LexerConfig, TokenType};
+
+    const CODE: &str = "WHILE If if x;";
+
+    #[test]
+    fn insensitive() {
+        let config = LexerConfig {
+            case_insensitive_keywords: true,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", CODE, config);
+        assert_eq!(
+            tokens.types.as_slice(),
+            &[
+                TokenType::While,
+                TokenType::If,
+                TokenType::If,
+                TokenType::Ident,
+                TokenType::Semi,
+            ]
+        );
+
+        let slices: Vec<_> = tokens.spans.iter().map(|s| s.slice).collect();
+        assert_eq!(slices, ["WHILE", "If", "if", "x", ";"]);
+    }
+
+    #[test]
+    fn sensitive() {
+        let tokens = lex("test", CODE);
+        assert_eq!(
+            tokens.types.as_slice(),
+            &[
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::If,
+                TokenType::Ident,
+                TokenType::Semi,
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_shebang {
+    use crate::lexer::{lex, try_lex_at_offset, LexError, TokenType};
+
+    #[test]
+    fn skipped() {
+        let tokens = lex("test", "#!/usr/bin/env cminus\nmain();");
+        assert_eq!(tokens.types[0], TokenType::Ident);
+        assert_eq!(tokens.spans[0].slice, "main");
+        assert_eq!(tokens.spans[0].line, 2);
+        assert_eq!(tokens.line_breaks.as_slice(), &[21]);
+    }
+
+    #[test]
+    #[should_panic(expected = "test:2:1: Cannot parse token")]
+    fn only_at_start() {
+        lex("test", "main();\n#!/usr/bin/env cminus\n");
+    }
+
+    #[test]
+    fn not_in_fragments() {
+        let err = try_lex_at_offset("#!x", 3, 2, 40).unwrap_err();
+        assert_eq!(err, LexError::CannotParseToken { line: 3, col: 3 });
+
+        // unless the fragment starts the file
+        let tokens = try_lex_at_offset("#!x\ny", 1, 0, 0).unwrap();
+        assert_eq!(tokens.spans[0].slice, "y");
+    }
+}
+
+#[cfg(test)]
+mod tests_lsp {
+    use crate::{
+        lexer::{try_lex_bytes, try_lex_bytes_with_config, LexError, LexerConfig},
+        lsp::{LspPosition, LspRange, LspSeverity},
+    };
+
+    #[test]
+    fn zero_based() {
+        let (tokens, err) = try_lex_bytes(b"a;\n  b ` c;\n");
+        let err = err.unwrap();
+        assert_eq!(err, LexError::CannotParseToken { line: 2, col: 5 });
+        let diagnostic = err.to_lsp_diagnostic(&tokens);
+
+        assert_eq!(
+            diagnostic.range,
+            LspRange {
+                start: LspPosition {
+                    line: 1,
+                    character: 4,
+                },
+                end: LspPosition {
+                    line: 1,
+                    character: 5,
+                },
+            }
+        );
+        assert_eq!(diagnostic.severity, LspSeverity::Error);
+        assert_eq!(diagnostic.message, "Cannot parse token");
+    }
+
+    #[test]
+    fn utf16_columns() {
+        // 'ඞ' is 3 bytes in UTF-8 but a single UTF-16 code unit
+        let (tokens, err) = try_lex_bytes("x := 'ඞ' ` 1;".as_bytes());
+        let err = err.unwrap();
+        assert_eq!(err, LexError::CannotParseToken { line: 1, col: 12 });
+        let range = err.to_lsp_diagnostic(&tokens).range;
+
+        assert_eq!(range.start.character, 9);
+        assert_eq!(range.end.character, 10);
+    }
+
+    #[test]
+    fn tab_columns() {
+        let config = LexerConfig {
+            tab_width: 4,
+            ..LexerConfig::default()
+        };
+        let mut buf = String::new();
+        let (tokens, err) = try_lex_bytes_with_config(b"x;\n\t\ta `", config, &mut buf);
+        let err = err.unwrap();
+        assert_eq!(err, LexError::CannotParseToken { line: 2, col: 11 });
+        let range = err.to_lsp_diagnostic(&tokens).range;
+
+        assert_eq!((range.start.line, range.start.character), (1, 4));
+        assert_eq!(range.end.character, 5);
+    }
+}
+
+#[cfg(test)]
+mod tests_string_value {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn line_continuation() {
+        let tokens = lex("test", "x := \"hello \\\nworld\";\ny;");
+        let idx = tokens.positions(TokenType::String).next().unwrap();
+
+        assert_eq!(tokens.string_value(idx).unwrap().unwrap(), "hello world");
+        assert_eq!(tokens.line_breaks.len(), 2);
+        assert_eq!(tokens.spans[idx + 1].line, 2);
+        assert_eq!(tokens.last().unwrap().2, 3);
+    }
+
+    #[test]
+    fn escaped_backslash_and_plain() {
+        let tokens = lex("test", "b\"a\\tb\" \"\\\r\nc\";");
+
+        assert_eq!(tokens.string_value(0).unwrap().unwrap(), "a\\tb");
+        assert_eq!(tokens.string_value(1).unwrap().unwrap(), "c");
+        assert_eq!(tokens.string_value(2), None);
+    }
+}
+
+#[cfg(test)]
+mod tests_block_string {
+    use crate::lexer::{lex, next_token, LexError, LexState, TokenType};
+
+    #[test]
+    fn strips_indentation() {
+        let code = "x := \"\"\"\n    hello\n      world\n\n    end\n    \"\"\";\n";
+        let tokens = lex("test", code);
+        let idx = tokens.positions(TokenType::String).next().unwrap();
+
+        assert_eq!(
+            tokens.block_string_value(idx).unwrap(),
+            "hello\n  world\n\nend"
+        );
+        assert_eq!(tokens.last().unwrap().2, 6);
+    }
+
+    #[test]
+    fn single_line() {
+        let tokens = lex("test", "\"\"\"x\"\"\";");
+
+        assert_eq!(tokens.types[0], TokenType::String);
+        assert_eq!(tokens.block_string_value(0).unwrap(), "x");
+        assert_eq!(tokens.string_value(0).unwrap().unwrap(), "x");
+        assert_eq!(tokens.block_string_value(1), None);
+    }
+
+    #[test]
+    fn unterminated() {
+        let mut state = LexState::new();
+        let result = next_token(b"\"\"\"abc\n\"\"", &mut state);
+
+        assert_eq!(
+            result,
+            Some(Err(LexError::UnfinishedString { line: 1, col: 1 }))
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_panic_message {
+    use std::panic;
+
+    use crate::lexer::{lex, lex_with_config, LexerConfig};
+
+    fn panic_message(f: impl FnOnce() + panic::UnwindSafe) -> String {
+        let payload = panic::catch_unwind(f).unwrap_err();
+        *payload.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    fn caret() {
+        let msg = panic_message(|| {
+            lex("test.sus", "a;\nfoo ` bar;");
+        });
+        assert_eq!(msg, "test.sus:2:5: Cannot parse token\nfoo ` bar;\n    ^");
+
+        let msg = panic_message(|| {
+            lex("test.sus", "x := \"ඞ;\n");
+        });
+        assert!(
+            msg.starts_with("test.sus:1:6: Unfinished string\n"),
+            "{msg}"
+        );
+        assert!(msg.ends_with("x := \"ඞ;\n     ^"), "{msg}");
+    }
+
+    #[test]
+    fn tabs() {
+        let config = LexerConfig {
+            tab_width: 4,
+            ..Default::default()
+        };
+        let msg = panic_message(|| {
+            lex_with_config("test.sus", "\t\"ඞ\" `", config);
+        });
+        assert!(msg.starts_with("test.sus:1:11: "), "{msg}");
+        assert!(msg.ends_with("\t\"ඞ\" `\n\t    ^"), "{msg}");
+    }
+}
+
+#[cfg(test)]
+mod tests_comment_syntax {
+    use crate::lexer::{lex, lex_with_config, try_lex, LexError, LexerConfig, TokenType};
+
+    #[test]
+    fn hash_line_comments() {
+        let config = LexerConfig {
+            line_comment: "#",
+            block_comment: None,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "# x\ny", config);
+        assert_eq!(tokens.types.as_slice(), [TokenType::Ident]);
+        assert_eq!((tokens.spans[0].slice, tokens.spans[0].line), ("y", 2));
+
+        // the default delimiters are just operators now
+        let tokens = lex_with_config("test", "a // b /* c */", config);
+        assert_eq!(tokens.types.len(), 9);
+    }
+
+    #[test]
+    fn word_delimiter() {
+        let config = LexerConfig {
+            line_comment: "rem",
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "remark = 1; rem note\nrem\nx", config);
+        let slices: Vec<&str> = tokens.spans.iter().map(|span| span.slice).collect();
+        assert_eq!(slices, ["remark", "=", "1", ";", "x"]);
+    }
+
+    #[test]
+    fn block_comments() {
+        let tokens = lex("test", "a /* b\n c */ d /**/e");
+        let slices: Vec<&str> = tokens.spans.iter().map(|span| span.slice).collect();
+        assert_eq!(slices, ["a", "d", "e"]);
+        assert_eq!(tokens.spans[1].line, 2);
+
+        let config = LexerConfig {
+            block_comment: Some(("(*", "*)")),
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "a (* b *) c", config);
+        assert_eq!(tokens.types.len(), 2);
+    }
+
+    #[test]
+    fn unfinished_block_comment() {
+        assert_eq!(
+            try_lex("a;\n  /* b").unwrap_err(),
+            LexError::UnfinishedComment { line: 2, col: 3 }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_eof {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn token_at_eof() {
+        for (code, ty) in [
+            ("a", TokenType::Ident),
+            ("loop", TokenType::Loop),
+            ("12", TokenType::Num),
+            ("0x1f", TokenType::Num),
+            ("0o7", TokenType::Num),
+            ("0b1", TokenType::Num),
+            ("1.", TokenType::Num),
+            ("2.5e", TokenType::Num),
+            ("3e-", TokenType::Num),
+        ] {
+            let tokens = lex("test", code);
+            assert_eq!(tokens.types.as_slice(), [ty], "{code}");
+            assert_eq!(tokens.spans[0].slice, code);
+        }
+    }
+
+    #[test]
+    fn comment_at_eof() {
+        let tokens = lex("test", "a; // c");
+        assert_eq!(tokens.types.len(), 2);
+    }
+
+    #[test]
+    fn comment_then_line_break() {
+        let tokens = lex("test", "a // c\nb");
+        assert_eq!(tokens.spans[0].line, 1);
+        assert_eq!(tokens.spans[1].line, 2);
+        assert_eq!(tokens.spans[1].col, 0);
+        assert_eq!(tokens.line_breaks.as_slice(), [6]);
+    }
+}
+
+#[cfg(test)]
+mod tests_same_tokens {
+    use crate::lexer::lex;
+
+    #[test]
+    fn ignores_positions() {
+        let tokens = lex("test", "a+b;");
+        assert!(tokens.same_tokens(&lex("test", "a  +  b;")));
+        assert!(tokens.same_tokens(&lex("test", "\n a\n+ // plus\nb ;")));
+        assert!(!tokens.same_tokens(&lex("test", "a-b;")));
+        assert!(!tokens.same_tokens(&lex("test", "a+c;")));
+        assert!(!tokens.same_tokens(&lex("test", "a+b")));
+    }
+}
+
+#[cfg(test)]
+mod tests_progress {
+    use crate::lexer::{lex_with_progress, PROGRESS_INTERVAL};
+
+    #[test]
+    fn monotonic() {
+        let code = "abc + 123;\n".repeat(3 * PROGRESS_INTERVAL / 11);
+
+        let mut reports = Vec::new();
+        let tokens = lex_with_progress("test", &code, &mut |fraction| reports.push(fraction));
+
+        assert_eq!(tokens.types.len(), code.len() / 11 * 4);
+        assert!(reports.len() >= 3, "{reports:?}");
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reports.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn small_input() {
+        let mut reports = Vec::new();
+        lex_with_progress("test", "a;", &mut |fraction| reports.push(fraction));
+        assert_eq!(reports, [1.0]);
+    }
+
+    #[test]
+    fn ends_on_interval() {
+        let code = "a;".repeat(PROGRESS_INTERVAL);
+
+        let mut reports = Vec::new();
+        lex_with_progress("test", &code, &mut |fraction| reports.push(fraction));
+        assert_eq!(reports, [0.5, 1.0]);
+    }
+}
+
+#[cfg(test)]
+mod tests_recover_errors {
+    use crate::lexer::{lex_with_config, LexerConfig, TokenType};
+
+    #[test]
+    fn error_token() {
+        let config = LexerConfig {
+            recover_errors: true,
+            ..Default::default()
+        };
+
+        let tokens = lex_with_config("test", "a ` b", config);
+        assert_eq!(
+            tokens.types.as_slice(),
+            [TokenType::Ident, TokenType::Error, TokenType::Ident]
+        );
+        assert_eq!(tokens.spans[0].slice, "a");
+        assert_eq!(tokens.spans[1].slice, "`");
+        assert_eq!(tokens.spans[2].slice, "b");
+
+        let tokens = lex_with_config("test", "ඞ`", config);
+        assert_eq!(tokens.types.as_slice(), [TokenType::Error; 2]);
+        assert_eq!(tokens.spans[0].slice, "ඞ");
+        assert_eq!(tokens.spans[1].offset, 3);
+    }
+}
+
+#[cfg(test)]
+mod tests_lexeme_len {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn fixed_tokens() {
+        assert_eq!(TokenType::Plus.lexeme_len(), Some(1));
+        assert_eq!(TokenType::Equals.lexeme_len(), Some(2));
+        assert_eq!(TokenType::Struct.lexeme_len(), Some(6));
+        assert_eq!(TokenType::Continue.lexeme_len(), Some(8));
+        assert_eq!(TokenType::Ident.lexeme_len(), None);
+        assert_eq!(TokenType::String.lexeme_len(), None);
+    }
+
+    #[test]
+    fn matches_lexer() {
+        let tokens = lex("misc.sus", include_str!("../tests/corpus/misc.sus"));
+        for (&ty, span) in tokens.types.iter().zip(tokens.spans.iter()) {
+            if let Some(len) = ty.lexeme_len() {
+                assert_eq!(len, span.slice.len(), "{ty:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_kind_histogram {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn counts() {
+        let tokens = lex("misc.sus", include_str!("../tests/corpus/misc.sus"));
+        let histogram = tokens.kind_histogram();
+
+        assert_eq!(histogram.iter().sum::<usize>(), tokens.types.len());
+        assert_eq!(histogram[TokenType::Semi as usize], 19);
+        assert_eq!(histogram[TokenType::Loop as usize], 1);
+        assert_eq!(histogram[TokenType::Error as usize], 0);
+        assert_eq!(histogram.len(), TokenType::COUNT);
+    }
+}
+
+#[cfg(test)]
+mod tests_span_lc {
+    use crate::lexer::{lex, lex_with_config, LexerConfig};
+
+    #[test]
+    fn single_line() {
+        let tokens = lex("test", "x\n  foo := 5;");
+        assert_eq!(tokens.span_lc(1), ((2, 2), (2, 5)));
+        assert_eq!(tokens.span_lc(2), ((2, 6), (2, 7)));
+    }
+
+    #[test]
+    fn multi_line_string() {
+        let tokens = lex("test", "s := \"ab\ncd\nefg\";");
+        assert_eq!(tokens.span_lc(3), ((1, 5), (3, 4)));
+
+        // the next token starts where the string ends
+        assert_eq!(tokens.span_lc(4).0, (3, 4));
+    }
+
+    #[test]
+    fn tabs() {
+        let config = LexerConfig {
+            tab_width: 4,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "\ts := \"a\tb\";\n\"\tc\";", config);
+        assert_eq!(tokens.span_lc(3), ((1, 9), (1, 14)));
+        assert_eq!(tokens.span_lc(4).0, (1, 14));
+        assert_eq!(tokens.span_lc(5), ((2, 0), (2, 6)));
+    }
+}
+
+#[cfg(test)]
+mod tests_adjacent {
+    use crate::lexer::lex;
+
+    #[test]
+    fn touching() {
+        let tokens = lex("test", "a+b");
+        assert!(tokens.adjacent(0, 1));
+        assert!(tokens.adjacent(1, 2));
+        // order matters
+        assert!(!tokens.adjacent(1, 0));
+        assert!(!tokens.adjacent(0, 2));
+    }
+
+    #[test]
+    fn whitespace_between() {
+        let tokens = lex("test", "a + b\nc// x\n;");
+        assert!(!tokens.adjacent(0, 1));
+        assert!(!tokens.adjacent(1, 2));
+        assert!(!tokens.adjacent(2, 3));
+        // comments count as something in between
+        assert!(!tokens.adjacent(3, 4));
+    }
+}
+
+#[cfg(test)]
+mod tests_bom {
+    use crate::lexer::{try_lex, try_lex_bytes, LexError, TokenType, Tokens};
+
+    #[test]
+    fn skipped() {
+        let tokens = try_lex("\u{FEFF}main :: 5;").unwrap();
+        assert_eq!(tokens.code, "main :: 5;");
+        assert_eq!(tokens.types[0], TokenType::Ident);
+        assert_eq!(tokens.spans[0].slice, "main");
+        assert_eq!((tokens.spans[0].offset, tokens.spans[0].col), (0, 0));
+
+        let (bytes, err) = try_lex_bytes(b"\xEF\xBB\xBFmain :: 5;");
+        assert_eq!(err, None);
+        assert_eq!(bytes.code, tokens.code);
+        assert_eq!(bytes.spans[0].offset, 0);
+
+        // invalid bytes are reported relative to the code after it too
+        let (_, err) = try_lex_bytes(b"\xEF\xBB\xBFa \xff");
+        assert_eq!(err, Some(LexError::InvalidUtf8 { line: 1, col: 3 }));
+    }
+
+    #[test]
+    fn only_at_start() {
+        let err = try_lex("a \u{FEFF}").unwrap_err();
+        assert_eq!(err, LexError::CannotParseToken { line: 1, col: 3 });
+    }
+
+    #[test]
+    fn deserialize() {
+        let code = "\u{FEFF}x + 1;";
+        let tokens = try_lex(code).unwrap();
+
+        let mut bytes = Vec::new();
+        tokens.serialize(&mut bytes).unwrap();
+        let cached = Tokens::deserialize(&mut bytes.as_slice(), code).unwrap();
+        assert_eq!(cached.code, "x + 1;");
+        assert_eq!(cached.spans[1].offset, tokens.spans[1].offset);
+    }
+}
+
+#[cfg(test)]
+mod tests_unfinished_string {
+    use crate::lexer::{try_lex, LexError};
+
+    #[test]
+    fn points_at_opening_quote() {
+        let code = "a;\nb := \"x\ny\nz";
+        assert_eq!(
+            try_lex(code).unwrap_err(),
+            LexError::UnfinishedString { line: 2, col: 6 }
+        );
+
+        // block strings
+        let code = "a;\n\n  \"\"\"\nx\n\"\"";
+        assert_eq!(
+            try_lex(code).unwrap_err(),
+            LexError::UnfinishedString { line: 3, col: 3 }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_lex_bytes {
+    use crate::lexer::{
+        try_lex_bytes, try_lex_bytes_with_config, LexError, LexerConfig, TokenType,
+    };
+
+    #[test]
+    fn invalid_byte() {
+        let (tokens, err) = try_lex_bytes(b"abc;\nfoo \xff bar;");
+        assert_eq!(err, Some(LexError::InvalidUtf8 { line: 2, col: 5 }));
+
+        // the code before the invalid byte is still lexed
+        assert_eq!(tokens.code, "abc;\nfoo ");
+        assert_eq!(
+            tokens.types.as_slice(),
+            [TokenType::Ident, TokenType::Semi, TokenType::Ident]
+        );
+    }
+
+    #[test]
+    fn valid_utf8() {
+        let (tokens, err) = try_lex_bytes(b"foo bar;");
+        assert_eq!(err, None);
+        assert_eq!(tokens.types.len(), 3);
+
+        // multibyte characters are valid, but still have to be lexable
+        let (tokens, err) = try_lex_bytes("a ඞ= b;".as_bytes());
+        assert_eq!(err, Some(LexError::CannotParseToken { line: 1, col: 3 }));
+        assert_eq!(tokens.types.len(), 1);
+    }
+
+    #[test]
+    fn recover() {
+        let config = LexerConfig {
+            recover_errors: true,
+            ..LexerConfig::default()
+        };
+        let mut buf = String::new();
+        let (tokens, err) = try_lex_bytes_with_config(b"a = \xff\xfe;\nb", config, &mut buf);
+        assert_eq!(err, None);
+
+        assert_eq!(tokens.code, "a = \u{FFFD}\u{FFFD};\nb");
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::Ident,
+                TokenType::Equal,
+                TokenType::Error,
+                TokenType::Error,
+                TokenType::Semi,
+                TokenType::Ident,
+            ]
+        );
+        assert_eq!(tokens.spans[2].slice, "\u{FFFD}");
+        assert_eq!((tokens.spans[5].line, tokens.spans[5].col), (2, 0));
+    }
+}
+
+#[cfg(test)]
+mod tests_at {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn attribute() {
+        let tokens = lex("test", "@inline fn");
+        assert_eq!(
+            tokens.types.as_slice(),
+            [TokenType::At, TokenType::Ident, TokenType::Fn]
+        );
+        assert_eq!(tokens.spans[1].slice, "inline");
+    }
+
+    #[test]
+    fn at_eof() {
+        let tokens = lex("test", "x @");
+        assert_eq!(tokens.last().unwrap(), (TokenType::At, "@", 1, 2));
+    }
+}
+
+#[cfg(test)]
+mod tests_filter {
+    use crate::lexer::{lex_with_config, LexerConfig, TokenType};
+
+    #[test]
+    fn drop_errors() {
+        let config = LexerConfig {
+            recover_errors: true,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "a ` b;\n`c;", config);
+        let filtered = tokens.filter(|ty| ty != TokenType::Error);
+
+        assert_eq!(tokens.types.len(), 7);
+        assert_eq!(filtered.types.len(), 5);
+        assert!(!filtered.contains_type(TokenType::Error));
+        assert_eq!(
+            filtered.line_breaks.as_slice(),
+            tokens.line_breaks.as_slice()
+        );
+
+        let kept = tokens.positions(TokenType::Error).collect::<Vec<_>>();
+        let kept = (0..tokens.types.len()).filter(|idx| !kept.contains(idx));
+        for (filtered_idx, idx) in kept.enumerate() {
+            let (span, filtered_span) = (&tokens.spans[idx], &filtered.spans[filtered_idx]);
+            assert_eq!(span.slice, filtered_span.slice);
+            assert_eq!(span.offset, filtered_span.offset);
+            assert_eq!(
+                (span.line, span.col),
+                (filtered_span.line, filtered_span.col)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_mixed_indentation {
+    use crate::lexer::lex;
+
+    #[test]
+    fn lines() {
+        let code = "a;\n\tb;\n    c;\n\t  d;\n  \te;\n \t\n\t\tf; \t g;";
+        let tokens = lex("test", code);
+        assert_eq!(tokens.mixed_indentation_lines(), [4, 5]);
+    }
+
+    #[test]
+    fn consistent() {
+        let tabs = lex("test", "a {\n\tb;\n\t\tc;\n}");
+        assert!(tabs.mixed_indentation_lines().is_empty());
+
+        let spaces = lex("test", "a {\r\n    b;\r\n}\r\n");
+        assert!(spaces.mixed_indentation_lines().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests_map_types {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn contextual_keyword() {
+        let mut tokens = lex("test", "result := f(result, results);");
+        let before = tokens.types.as_slice().to_vec();
+
+        tokens.map_types(|ty, text| match (ty, text) {
+            (TokenType::Ident, "result") => TokenType::Defer,
+            _ => ty,
+        });
+
+        for (idx, (&ty, &old)) in tokens.types.iter().zip(&before).enumerate() {
+            match tokens.spans[idx].slice {
+                "result" => assert_eq!(ty, TokenType::Defer),
+                _ => assert_eq!(ty, old),
+            }
+        }
+        assert_eq!(tokens.types[0], TokenType::Defer);
+        assert_eq!(tokens.types[7], TokenType::Ident);
+    }
+}
+
+#[cfg(test)]
+mod tests_number_radix {
+    use crate::lexer::{lex, Radix};
+
+    #[test]
+    fn prefixes() {
+        let tokens = lex("test", "0xFF 0o17 0b1 42 0 0x1.8p3 7u8 x");
+        let radixes: Vec<_> = (0..tokens.types.len())
+            .map(|idx| tokens.number_radix(idx))
+            .collect();
+        assert_eq!(
+            radixes,
+            [
+                Some(Radix::Hex),
+                Some(Radix::Oct),
+                Some(Radix::Bin),
+                Some(Radix::Dec),
+                Some(Radix::Dec),
+                Some(Radix::Hex),
+                Some(Radix::Dec),
+                None,
+            ]
+        );
+        assert_eq!(Radix::Oct.base(), 8);
+    }
+}
+
+#[cfg(test)]
+mod tests_num_suffix {
+    use crate::lexer::{lex, try_lex, LexError, NumSuffix, TokenType};
+
+    #[test]
+    fn suffixes() {
+        let tokens = lex("test", "255u8 1.0f32 5 1_000_i128 0xffu16 0x1f32;");
+        assert_eq!(tokens.types.len(), 7);
+        assert_eq!(tokens.spans[0].slice, "255u8");
+
+        let suffixes = (0..6).map(|idx| tokens.number_suffix(idx));
+        assert_eq!(
+            suffixes.collect::<Vec<_>>(),
+            [
+                Some(NumSuffix::U8),
+                Some(NumSuffix::F32),
+                None,
+                Some(NumSuffix::I128),
+                Some(NumSuffix::U16),
+                None,
+            ]
+        );
+        assert_eq!(tokens.number_suffix(6), None);
+        assert_eq!(NumSuffix::I128.as_str(), "i128");
+    }
+
+    #[test]
+    fn unknown_suffix() {
+        assert_eq!(
+            try_lex("x := 10q;").unwrap_err(),
+            LexError::InvalidNumSuffix { line: 1, col: 8 }
+        );
+        assert_eq!(
+            try_lex("0b102").unwrap_err(),
+            LexError::InvalidNumSuffix { line: 1, col: 5 }
+        );
+        assert_eq!(lex("test", "10 q").types[1], TokenType::Ident);
+    }
+}
+
+#[cfg(test)]
+mod tests_span {
+    use crate::lexer::lex;
+
+    #[test]
+    fn from_token() {
+        let code = "a := 1;\nfoo(bar);";
+        let tokens = lex("test", code);
+
+        let span = tokens.spans[7].span();
+        assert_eq!(&code[span.start..span.end], "bar");
+        assert_eq!((span.line, span.col), (2, 4));
+
+        let call = tokens.spans[5].span().to(tokens.spans[8].span());
+        assert_eq!(&code[call.start..call.end], "foo(bar)");
+        assert_eq!(call.len(), 8);
+        assert_eq!((call.line, call.col), (2, 0));
+    }
+}
+
+#[cfg(test)]
+mod tests_from_str {
+    use crate::lexer::{TokenType, UnknownLexeme};
+
+    #[test]
+    fn lexemes() {
+        assert_eq!("struct".parse(), Ok(TokenType::Struct));
+        assert_eq!("+".parse(), Ok(TokenType::Plus));
+        assert_eq!("**".parse(), Ok(TokenType::Pow));
+        assert_eq!("@".parse(), Ok(TokenType::At));
+    }
+
+    #[test]
+    fn not_lexemes() {
+        for s in [
+            "notatoken",
+            "loopy",
+            "a",
+            "12",
+            "\"s\"",
+            "+ ",
+            " +",
+            "",
+            "++x",
+        ] {
+            assert_eq!(s.parse::<TokenType>(), Err(UnknownLexeme), "{s:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_tab_width {
+    use crate::lexer::{lex, lex_with_config, try_lex_with_config, LexError, LexerConfig};
+
+    const CONFIG: LexerConfig = LexerConfig {
+        case_insensitive_keywords: false,
+        recover_errors: false,
+        tab_width: 4,
+        interpolate_plain_strings: false,
+        line_comment: "//",
+        block_comment: Some(("/*", "*/")),
+    };
+
+    #[test]
+    fn tab_stops() {
+        let tokens = lex_with_config("test", "\tx;\n  \ty\t=\t1;", CONFIG);
+        let cols: Vec<_> = tokens.spans.iter().map(|span| span.col).collect();
+        assert_eq!(cols, [4, 5, 4, 8, 12, 13]);
+
+        // the default counts tabs as one column
+        let tokens = lex("test", "\tx;");
+        assert_eq!(tokens.spans[0].col, 1);
+    }
+
+    #[test]
+    fn error_column() {
+        assert_eq!(
+            try_lex_with_config("\t\t`", CONFIG).unwrap_err(),
+            LexError::CannotParseToken { line: 1, col: 9 }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_underscore {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn wildcard() {
+        let tokens = lex("test", "_ _x __ x_ _;");
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::Underscore,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Underscore,
+                TokenType::Semi,
+            ]
+        );
+        assert_eq!(tokens.spans[2].slice, "__");
+        assert_eq!("_".parse(), Ok(TokenType::Underscore));
+    }
+}
+
+#[cfg(test)]
+mod tests_max_line_len {
+    use crate::lexer::lex;
+
+    #[test]
+    fn longest_line() {
+        assert_eq!(lex("test", "").max_line_len(), 0);
+        assert_eq!(lex("test", "a + b;").max_line_len(), 6);
+        assert_eq!(lex("test", "a;\nlonger;\nb;").max_line_len(), 7);
+        assert_eq!(lex("test", "a;\nb;\nlast line;").max_line_len(), 10);
+        assert_eq!(lex("test", "first line;\n\n").max_line_len(), 11);
+        assert_eq!(lex("test", "'ඞ';").max_line_len(), 6);
+    }
+}
+
+#[cfg(test)]
+mod tests_tokens_in_range {
+    use crate::lexer::lex;
+
+    #[test]
+    fn edits() {
+        // 0..3 foo, 4 +, 6..9 bar, 9 ;
+        let tokens = lex("test", "foo + bar; // done");
+
+        // inside one token
+        assert_eq!(tokens.tokens_in_range(1..2), 0..1);
+        assert_eq!(tokens.tokens_in_range(7..7), 2..3);
+        // spanning two tokens
+        assert_eq!(tokens.tokens_in_range(2..5), 0..2);
+        assert_eq!(tokens.tokens_in_range(8..10), 2..4);
+        // in whitespace or a comment
+        assert_eq!(tokens.tokens_in_range(5..6), 2..2);
+        assert_eq!(tokens.tokens_in_range(12..14), 4..4);
+        // at the edges of tokens
+        assert_eq!(tokens.tokens_in_range(3..4), 1..1);
+        assert_eq!(tokens.tokens_in_range(6..6), 2..3);
+        assert_eq!(tokens.tokens_in_range(5..5), 1..2);
+        assert_eq!(tokens.tokens_in_range(12..12), 4..4);
+        assert_eq!(tokens.tokens_in_range(0..18), 0..4);
+    }
+
+    #[test]
+    fn insert_between_tokens() {
+        // inserting `c` between `a` and `+` would make them `ac+`
+        let tokens = lex("test", "a+b");
+        assert_eq!(tokens.tokens_in_range(1..1), 0..2);
+        assert_eq!(tokens.tokens_in_range(0..0), 0..1);
+        assert_eq!(tokens.tokens_in_range(3..3), 2..3);
+    }
+}
+
+#[cfg(test)]
+mod tests_context {
+    use crate::lexer::lex;
+
+    #[test]
+    fn clamped_to_line() {
+        let tokens = lex("test", "a := 1;\nfoo(bar);\nz;");
+        // `foo` starts the second line
+        assert_eq!(tokens.context(5, 3), "foo(ba");
+        // `;` ends it
+        assert_eq!(tokens.context(9, 3), "ar);");
+        // `(` is in the middle
+        assert_eq!(tokens.context(6, 2), "oo(ba");
+        assert_eq!(tokens.context(6, 100), "foo(bar);");
+        assert_eq!(tokens.context(6, 0), "(");
+    }
+
+    #[test]
+    fn char_boundaries() {
+        let tokens = lex("test", "x := 'ඞ' + y;");
+        // 3 bytes before `+` land inside `ඞ`
+        assert_eq!(tokens.context(4, 3), "' + y;");
+    }
+}
+
+#[cfg(test)]
+mod tests_check_control_flow {
+    use crate::lexer::{lex, ControlFlowError};
+
+    #[test]
+    fn balanced() {
+        let tokens = lex(
+            "test",
+            "x := if a then { if b then 1 else 2 } else if c then 3 else 4;",
+        );
+        assert_eq!(tokens.check_control_flow(), Ok(()));
+    }
+
+    #[test]
+    fn dangling_else() {
+        let tokens = lex("test", "if a then 1 else 2;\nelse 3;");
+        assert_eq!(
+            tokens.check_control_flow(),
+            Err(ControlFlowError::ElseWithoutThen { line: 2, col: 1 })
+        );
+    }
+
+    #[test]
+    fn then_without_if() {
+        let tokens = lex("test", "if a { then 1 }");
+        assert_eq!(
+            tokens.check_control_flow(),
+            Err(ControlFlowError::ThenWithoutIf { line: 1, col: 8 })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_hex_float {
+    use crate::lexer::{lex, try_lex, LexError, NumSuffix, TokenType};
+
+    #[test]
+    fn hex_floats() {
+        let tokens = lex("test", "0x1.8p3 0x1p-2 0xA.bP+4f64 0x1.len;");
+        let slices: Vec<&str> = tokens.spans.iter().map(|span| span.slice).collect();
+        assert_eq!(
+            slices,
+            ["0x1.8p3", "0x1p-2", "0xA.bP+4f64", "0x1", ".", "len", ";"]
+        );
+        assert_eq!(tokens.types.as_slice()[..3], [TokenType::Num; 3]);
+        assert_eq!(tokens.number_suffix(2), Some(NumSuffix::F64));
+    }
+
+    #[test]
+    fn missing_exponent() {
+        assert_eq!(
+            try_lex("x := 0x1.8;").unwrap_err(),
+            LexError::MissingHexExponent { line: 1, col: 11 }
+        );
+    }
+
+    #[test]
+    fn exponent_without_digits() {
+        for (code, col) in [("0x1p", 5), ("0x1p+", 6), ("0x1.8p;", 7)] {
+            assert_eq!(
+                try_lex(code).unwrap_err(),
+                LexError::CannotParseToken { line: 1, col },
+                "{code}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_debug_rows {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn rows() {
+        let tokens = lex("test", "a + b");
+        assert_eq!(
+            tokens.debug_rows(),
+            [
+                (1, 0, TokenType::Ident, "a"),
+                (1, 2, TokenType::Plus, "+"),
+                (1, 4, TokenType::Ident, "b"),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_logical_keywords {
+    use crate::lexer::{lex, lex_with_config, LexerConfig, TokenType};
+
+    #[test]
+    fn keywords_not_prefixes() {
+        let tokens = lex("test", "and or xor not band ornot anda loopy iff");
+        let types: Vec<TokenType> = tokens.types.iter().copied().collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::And,
+                TokenType::Or,
+                TokenType::Xor,
+                TokenType::Not,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Ident,
+            ]
+        );
+        assert!(types[..4].iter().all(|ty| ty.is_logical_keyword()));
+        assert!(!TokenType::Ampersand.is_logical_keyword());
+        assert!(!TokenType::Tilde.is_logical_keyword());
+    }
+
+    #[test]
+    fn case_insensitive_long_idents() {
+        let config = LexerConfig {
+            case_insensitive_keywords: true,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "CONTINUE continues Continue_", config);
+        assert_eq!(
+            tokens.types.as_slice(),
+            [TokenType::Continue, TokenType::Ident, TokenType::Ident]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_iter_significant {
+    use crate::lexer::{lex_with_config, LexerConfig, TokenType};
+
+    #[test]
+    fn skips_errors() {
+        let config = LexerConfig {
+            recover_errors: true,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", "a ` b;\n`c;", config);
+
+        let significant: Vec<&str> = tokens
+            .iter_significant()
+            .map(|(_, span)| span.slice)
+            .collect();
+        assert_eq!(significant, ["a", "b", ";", "c", ";"]);
+
+        let skip = [TokenType::Error, TokenType::Semi];
+        let types: Vec<TokenType> = tokens.iter_skipping(&skip).map(|(ty, _)| ty).collect();
+        assert_eq!(types, [TokenType::Ident; 3]);
+    }
+}
+
+#[cfg(test)]
+mod tests_diff_tokens {
+    use crate::lexer::{diff_tokens, lex, TokenDiff, TokenType};
+
+    #[test]
+    fn one_token() {
+        let a = lex("a", "x := y + 1;");
+        let b = lex("b", "x := z + 1;");
+        assert_eq!(
+            diff_tokens(&a, &b),
+            [TokenDiff::Text {
+                idx: 3,
+                a: "y",
+                b: "z"
+            }]
+        );
+        assert_eq!(diff_tokens(&a, &a), []);
+    }
+
+    #[test]
+    fn shifted() {
+        let a = lex("a", "f(x);");
+        let b = lex("b", "f(1);\n");
+        let c = lex("c", "f (x)");
+        assert_eq!(
+            diff_tokens(&a, &b),
+            [
+                TokenDiff::Type {
+                    idx: 2,
+                    a: TokenType::Ident,
+                    b: TokenType::Num
+                },
+                TokenDiff::Text {
+                    idx: 2,
+                    a: "x",
+                    b: "1"
+                },
+            ]
+        );
+        assert_eq!(
+            diff_tokens(&a, &c),
+            [
+                TokenDiff::Position {
+                    idx: 1,
+                    a: (1, 1),
+                    b: (1, 2)
+                },
+                TokenDiff::Position {
+                    idx: 2,
+                    a: (1, 2),
+                    b: (1, 3)
+                },
+                TokenDiff::Position {
+                    idx: 3,
+                    a: (1, 3),
+                    b: (1, 4)
+                },
+                TokenDiff::Len { a: 5, b: 4 },
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_first_on_line {
+    use crate::lexer::lex;
+
+    #[test]
+    fn lines() {
+        let tokens = lex("test", "a :: 1;\n    b;\n\nc; d;\n");
+        assert_eq!(tokens.first_on_line(1), Some(0));
+        assert_eq!(tokens.first_on_line(2), Some(5));
+        assert_eq!(tokens.first_on_line(3), None);
+        assert_eq!(tokens.first_on_line(4), Some(7));
+        assert_eq!(tokens.first_on_line(5), None);
+        assert_eq!(tokens.first_on_line(0), None);
+    }
+}
+
+#[cfg(test)]
+mod tests_keyword_set {
+    use crate::lexer::{try_lex_with_keywords, KeywordSet, TokenType};
+
+    #[test]
+    fn custom_keywords() {
+        let mut keywords = KeywordSet::default();
+        assert_eq!(keywords.insert("yeet", TokenType::Break), None);
+        assert_eq!(keywords.remove("loop"), Some(TokenType::Loop));
+
+        let tokens = try_lex_with_keywords("loop { yeet; } while x", &keywords).unwrap();
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::Ident,
+                TokenType::LBrace,
+                TokenType::Break,
+                TokenType::Semi,
+                TokenType::RBrace,
+                TokenType::While,
+                TokenType::Ident,
+            ]
+        );
+
+        let tokens = try_lex_with_keywords("if x then y", &KeywordSet::empty()).unwrap();
+        assert_eq!(tokens.types.as_slice(), [TokenType::Ident; 4]);
+    }
+}
+
+#[cfg(test)]
+mod tests_interpolate_plain_strings {
+    use crate::lexer::{lex, lex_with_config, LexerConfig, TokenType};
+
+    const CONFIG: LexerConfig = LexerConfig {
+        case_insensitive_keywords: false,
+        recover_errors: false,
+        tab_width: 1,
+        interpolate_plain_strings: true,
+        line_comment: "//",
+        block_comment: Some(("/*", "*/")),
+    };
+
+    #[test]
+    fn segments() {
+        let tokens = lex_with_config("test", "\"a {x} b\"", CONFIG);
+        assert_eq!(
+            tokens.debug_rows(),
+            [
+                (1, 0, TokenType::StringInterpBeg, "\"a {"),
+                (1, 4, TokenType::Ident, "x"),
+                (1, 5, TokenType::StringInterpEnd, "} b\""),
+            ]
+        );
+
+        // off by default
+        let tokens = lex("test", "\"a {x} b\"");
+        assert_eq!(tokens.types.as_slice(), [TokenType::String]);
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let tokens = lex_with_config("test", "\"{{literal}}\" \"{{{x}}}\"", CONFIG);
+        assert_eq!(
+            tokens.debug_rows(),
+            [
+                (1, 0, TokenType::String, "\"{{literal}}\""),
+                (1, 14, TokenType::StringInterpBeg, "\"{{{"),
+                (1, 18, TokenType::Ident, "x"),
+                (1, 19, TokenType::StringInterpEnd, "}}}\""),
+            ]
+        );
+
+        // `$"` strings keep treating `{{` as an expression part holding a block
+        let tokens = lex_with_config("test", "$\"{{x}}\"", CONFIG);
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::StringInterpBeg,
+                TokenType::LBrace,
+                TokenType::Ident,
+                TokenType::RBrace,
+                TokenType::StringInterpEnd,
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_iter_rev {
+    use crate::lexer::{lex, TokenType};
+
+    #[test]
+    fn reversed() {
+        let tokens = lex("test", "f(x);\ny");
+        let rows: Vec<_> = tokens
+            .iter_rev()
+            .map(|(ty, span)| (span.line, span.col, ty, span.slice))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (2, 0, TokenType::Ident, "y"),
+                (1, 4, TokenType::Semi, ";"),
+                (1, 3, TokenType::RParens, ")"),
+                (1, 2, TokenType::Ident, "x"),
+                (1, 1, TokenType::LParens, "("),
+                (1, 0, TokenType::Ident, "f"),
+            ]
+        );
+
+        let mut rows = tokens.debug_rows();
+        rows.reverse();
+        assert!(tokens
+            .iter_rev()
+            .map(|(ty, span)| (span.line, span.col, ty, span.slice))
+            .eq(rows));
+    }
+}
+
+#[cfg(test)]
+mod tests_control_char {
+    use crate::lexer::{try_lex, LexError};
+
+    #[test]
+    fn nul() {
+        let err = try_lex("x := 1;\ny\0;").unwrap_err();
+        assert_eq!(
+            err,
+            LexError::ControlChar {
+                byte: 0,
+                line: 2,
+                col: 2
+            }
+        );
+        assert_eq!(err.to_string(), "2:2: Control character 0x00");
+    }
+
+    #[test]
+    fn escape() {
+        let err = try_lex("a \x1b[0m").unwrap_err();
+        assert_eq!(
+            err,
+            LexError::ControlChar {
+                byte: 0x1b,
+                line: 1,
+                col: 3
+            }
+        );
+        assert_eq!(err.to_string(), "1:3: Control character 0x1B");
+    }
+}
+
+#[cfg(test)]
+mod tests_validate_spans {
+    use crate::lexer::{lex, TokenSpan, TokenType, Tokens};
+
+    #[test]
+    fn lexed() {
+        let tokens = lex("test", include_str!("../Cඞඞ.sus"));
+        assert_eq!(tokens.validate_spans(), Ok(()));
+    }
+
+    #[test]
+    fn inconsistent() {
+        let code = "a;\nb;";
+
+        let tokens = Tokens::new(code);
+        tokens.types.add(TokenType::Ident);
+        assert_eq!(
+            tokens.validate_spans(),
+            Err("1 types but 0 spans".to_string())
+        );
+
+        // text that isn't at its offset
+        tokens.spans.add(TokenSpan::new(&code[3..4], 0, 1, 0));
+        assert_eq!(
+            tokens.validate_spans(),
+            Err("span 0 is not at its offset in the code".to_string())
+        );
+
+        let tokens = Tokens::new(code);
+        tokens.types.add(TokenType::Ident);
+        tokens.types.add(TokenType::Ident);
+        tokens.spans.add(TokenSpan::new(&code[3..4], 3, 2, 0));
+        tokens.spans.add(TokenSpan::new(&code[0..1], 0, 1, 0));
+        assert_eq!(
+            tokens.validate_spans(),
+            Err("span 1 is on line 1 after line 2".to_string())
+        );
+
+        let tokens = Tokens::new(code);
+        tokens.line_breaks.add(1);
+        assert_eq!(
+            tokens.validate_spans(),
+            Err("line break at 1 is not a line break".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_unicode_escapes {
+    use crate::lexer::{lex, EscapeError};
+
+    #[test]
+    fn strings() {
+        let tokens = lex(
+            "test",
+            r#""\u{41}" "smile \u{1F600}!" "\u{110000}" "\u{D800}" "\u{}""#,
+        );
+        assert_eq!(tokens.string_value(0).unwrap().unwrap(), "A");
+        assert_eq!(tokens.string_value(1).unwrap().unwrap(), "smile 😀!");
+        assert_eq!(
+            tokens.string_value(2).unwrap(),
+            Err(EscapeError::OutOfRange {
+                offset: 29,
+                code_point: 0x110000
+            })
+        );
+        assert_eq!(
+            tokens.string_value(3).unwrap(),
+            Err(EscapeError::Surrogate {
+                offset: 42,
+                code_point: 0xD800
+            })
+        );
+        assert_eq!(
+            tokens.string_value(4).unwrap(),
+            Err(EscapeError::MalformedUnicode { offset: 53 })
+        );
+    }
+
+    #[test]
+    fn chars() {
+        let tokens = lex("test", r"'a' '\u{E9}' '\n' 'ඞ' 'ab' '\u{1234567}'");
+        assert_eq!(tokens.char_value(0), Some(Ok('a')));
+        assert_eq!(tokens.char_value(1), Some(Ok('é')));
+        assert_eq!(tokens.char_value(2), Some(Ok('\n')));
+        assert_eq!(tokens.char_value(3), Some(Ok('ඞ')));
+        assert_eq!(
+            tokens.char_value(4),
+            Some(Err(EscapeError::NotOneChar { offset: 25 }))
+        );
+        assert!(matches!(
+            tokens.char_value(5),
+            Some(Err(EscapeError::MalformedUnicode { .. }))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests_suspicious_operator_runs {
+    use crate::lexer::lex;
+
+    #[test]
+    fn runs() {
+        let tokens = lex("test", "x =+= 1;\ny = a + -b;\nz = ~-~");
+        assert_eq!(tokens.suspicious_operator_runs(), [1..4, 14..18]);
+    }
+}
+
+#[cfg(test)]
+mod tests_lex_at_offset {
+    use crate::lexer::{try_lex_at_offset, LexError, TokenType};
+
+    #[test]
+    fn shifted() {
+        let tokens = try_lex_at_offset("a\n  b", 10, 4, 100).unwrap();
+        assert_eq!(tokens.file_position(0), (100, 10, 4));
+        assert_eq!(tokens.file_position(1), (104, 11, 2));
+
+        // spans still index into the fragment
+        assert_eq!(tokens.debug_rows()[1], (2, 2, TokenType::Ident, "b"));
+        assert_eq!(tokens.merge_span(0..2), "a\n  b");
+        assert_eq!(tokens.tokens_in_range(4..5), 1..2);
+        assert!(tokens.validate_spans().is_ok());
+        assert_eq!(tokens.filter(|_| true).file_position(1), (104, 11, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Lines start at 1")]
+    fn line_zero() {
+        let _ = try_lex_at_offset("a", 0, 0, 0);
+    }
+
+    #[test]
+    fn shifted_errors() {
+        assert_eq!(
+            try_lex_at_offset("\"a", 3, 7, 0).unwrap_err(),
+            LexError::UnfinishedString { line: 3, col: 8 }
+        );
+        assert_eq!(
+            try_lex_at_offset("a\n'b", 3, 7, 0).unwrap_err(),
+            LexError::UnfinishedChar { line: 4, col: 1 }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_serialize {
+    use std::io::ErrorKind;
+
+    use crate::lexer::{diff_tokens, lex, write_varint, TokenType, Tokens, SERIALIZE_VERSION};
+
+    #[test]
+    fn round_trip() {
+        let code = include_str!("../Cඞඞ.sus");
+        let tokens = lex("Cඞඞ.sus", code);
+
+        let mut bytes = Vec::new();
+        tokens.serialize(&mut bytes).unwrap();
+        assert!(bytes.len() < code.len());
+
+        let read = Tokens::deserialize(&mut bytes.as_slice(), code).unwrap();
+        assert_eq!(diff_tokens(&tokens, &read), []);
+        assert_eq!(read.line_breaks.as_slice(), tokens.line_breaks.as_slice());
+    }
+
+    #[test]
+    fn rejects_other_code() {
+        let tokens = lex("test", "a + b;");
+        let mut bytes = Vec::new();
+        tokens.serialize(&mut bytes).unwrap();
+
+        let err = Tokens::deserialize(&mut bytes.as_slice(), "a + bc;").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        bytes[0] = b'X';
+        let err = Tokens::deserialize(&mut bytes.as_slice(), "a + b;").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        let mut bytes = b"SUSt".to_vec();
+        bytes.push(SERIALIZE_VERSION);
+        write_varint(&mut bytes, 2).unwrap();
+        // no line breaks, one token
+        write_varint(&mut bytes, 0).unwrap();
+        write_varint(&mut bytes, 1).unwrap();
+        bytes.push(TokenType::Ident as u8);
+        write_varint(&mut bytes, 1).unwrap();
+        write_varint(&mut bytes, usize::MAX).unwrap();
+        write_varint(&mut bytes, 0).unwrap();
+        write_varint(&mut bytes, 0).unwrap();
+
+        let err = Tokens::deserialize(&mut bytes.as_slice(), "ab").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Fails when [`TokenType`] changes, as a reminder to bump
+    /// `SERIALIZE_VERSION` along with the expected fingerprint.
+    #[test]
+    fn version_matches_token_types() {
+        // FNV-1a of the variant names in discriminant order
+        let mut fingerprint: u64 = 0xcbf29ce484222325;
+        for discriminant in 0..TokenType::COUNT as u8 {
+            // SAFETY: `TokenType` is `repr(u8)` and `Error` is its last variant
+            let ty: TokenType = unsafe { std::mem::transmute(discriminant) };
+            for byte in format!("{ty:?},").bytes() {
+                fingerprint = (fingerprint ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+
+        assert_eq!((SERIALIZE_VERSION, fingerprint), (1, 0x78fa4daa876ee53d));
+    }
+}
+
+#[cfg(test)]
+mod tests_indentation {
+    use crate::lexer::{lex, lex_with_config, LexerConfig};
+
+    const CODE: &str = "a {\n    b;\n\tc;\n  \td;\n\n   \n}\r\n";
+
+    #[test]
+    fn per_line() {
+        let tokens = lex("test", CODE);
+        let widths: Vec<usize> = (1..=8).map(|line| tokens.indentation(line)).collect();
+        assert_eq!(widths, [0, 4, 1, 3, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let config = LexerConfig {
+            tab_width: 4,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", CODE, config);
+        let widths: Vec<usize> = (1..=4).map(|line| tokens.indentation(line)).collect();
+        assert_eq!(widths, [0, 4, 4, 4]);
+        assert_eq!(tokens.filter(|_| true).indentation(3), 4);
+    }
+
+    #[test]
+    fn zero_tab_width() {
+        let config = LexerConfig {
+            tab_width: 0,
+            ..Default::default()
+        };
+        let tokens = lex_with_config("test", CODE, config);
+        let widths: Vec<usize> = (1..=4).map(|line| tokens.indentation(line)).collect();
+        assert_eq!(widths, [0, 4, 1, 3]);
+    }
 }
@@ -0,0 +1,9 @@
+pub mod arena;
+pub mod ast_arena;
+pub mod display;
+pub mod format;
+pub mod lexer;
+pub mod lsp;
+pub mod parser;
+pub mod source_map;
+pub mod visit;
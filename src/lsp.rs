@@ -0,0 +1,61 @@
+//! Diagnostics in the shape expected by the Language Server Protocol.
+//!
+//! LSP positions are zero-based and count columns in UTF-16 code units, while
+//! the lexer counts lines from 1 and columns in bytes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LspPosition {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LspSeverity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LspDiagnostic {
+    pub range: LspRange,
+    pub severity: LspSeverity,
+    pub message: String,
+}
+
+/// Converts a 1-based line and a 0-based byte column into an LSP position.
+pub fn position(code: &str, line: usize, col: usize) -> LspPosition {
+    let line_text = code.split('\n').nth(line - 1).unwrap_or("");
+    let prefix = line_text.get(..col).unwrap_or(line_text);
+
+    LspPosition {
+        line: line - 1,
+        character: prefix.encode_utf16().count(),
+    }
+}
+
+/// Range covering the character at a 1-based line and 0-based byte column.
+pub fn char_range(code: &str, line: usize, col: usize) -> LspRange {
+    let start = position(code, line, col);
+
+    let line_text = code.split('\n').nth(line - 1).unwrap_or("");
+    let width = line_text
+        .get(col..)
+        .and_then(|rest| rest.chars().next())
+        .map_or(0, char::len_utf16);
+
+    LspRange {
+        start,
+        end: LspPosition {
+            line: start.line,
+            character: start.character + width,
+        },
+    }
+}
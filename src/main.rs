@@ -1,9 +1,58 @@
-mod arena;
-mod lexer;
+use std::{collections::BTreeMap, env, fs, io};
+
+use csussus::{
+    display::{format_dump_to, TokenDump},
+    lexer::{self, Tokens},
+};
 
 const CODE: &str = include_str!("../Cඞඞ.sus");
 
 fn main() {
-    let tokens = lexer::lex("Cඞඞ.sus", CODE);
-    println!("{}", &tokens);
+    let mut count = false;
+    let mut prec = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--count" => count = true,
+            "--prec" => prec = true,
+            _ => path = Some(arg),
+        }
+    }
+
+    let code;
+    let (file_name, code) = match &path {
+        Some(path) => {
+            code = fs::read_to_string(path).unwrap_or_else(|err| panic!("{path}: {err}"));
+            (path.as_str(), code.as_str())
+        }
+        None => ("Cඞඞ.sus", CODE),
+    };
+
+    let tokens = lexer::lex(file_name, code);
+    if count {
+        print_counts(&tokens);
+    } else {
+        let mut dump = TokenDump::new(&tokens);
+        if prec {
+            dump = dump.with_precedence();
+        }
+        format_dump_to(io::stdout().lock(), dump).unwrap();
+    }
+}
+
+/// Prints how often each token type occurs, in the order the types are
+/// declared in.
+fn print_counts(tokens: &Tokens) {
+    let mut counts = BTreeMap::new();
+    for &ty in tokens.types.iter() {
+        *counts.entry(ty).or_insert(0usize) += 1;
+    }
+
+    let names: Vec<String> = counts.keys().map(|ty| format!("{ty:?}")).collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(5);
+
+    for (name, count) in names.iter().zip(counts.values()) {
+        println!("{name:<width$}   {count}");
+    }
+    println!("{:<width$}   {}", "Total", tokens.types.len());
 }
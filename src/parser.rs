@@ -0,0 +1,1667 @@
+use std::{fmt, mem};
+
+use crate::lexer::{TokenSpan, TokenType, Tokens};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr<'a> {
+    Ident(&'a str),
+    Num(&'a str),
+    String(&'a str),
+    Char(&'a str),
+    /// `[a, b, c]`
+    Array(Vec<Expr<'a>>),
+    /// `base[index]`
+    Index {
+        base: Box<Expr<'a>>,
+        index: Box<Expr<'a>>,
+    },
+    /// `base.field`
+    Field {
+        base: Box<Expr<'a>>,
+        field: &'a str,
+    },
+    /// `callee(a, b, c)`
+    Call {
+        callee: Box<Expr<'a>>,
+        args: Vec<Expr<'a>>,
+    },
+    /// `++expr`
+    PreIncr(Box<Expr<'a>>),
+    /// `--expr`
+    PreDecr(Box<Expr<'a>>),
+    /// `expr++`
+    PostIncr(Box<Expr<'a>>),
+    /// `expr--`
+    PostDecr(Box<Expr<'a>>),
+    /// `op expr`
+    Unary {
+        op: TokenType,
+        expr: Box<Expr<'a>>,
+    },
+    /// `lhs op rhs`
+    Binary {
+        op: TokenType,
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+    /// `{ stmts }`
+    Block(Vec<Stmt<'a>>),
+    /// `if cond then a else b`
+    If {
+        cond: Box<Expr<'a>>,
+        then: Box<Expr<'a>>,
+        else_: Option<Box<Expr<'a>>>,
+    },
+    /// `cond ? then : else_`
+    Ternary {
+        cond: Box<Expr<'a>>,
+        then: Box<Expr<'a>>,
+        else_: Box<Expr<'a>>,
+    },
+    /// `do body while cond`. The condition is checked after each run of the
+    /// body, so it always runs at least once.
+    DoWhile {
+        body: Box<Expr<'a>>,
+        cond: Box<Expr<'a>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    #[default]
+    Private,
+}
+
+/// `pub? name :: kind`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item<'a> {
+    pub visibility: Visibility,
+    pub name: &'a str,
+    pub kind: ItemKind<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemKind<'a> {
+    /// `packed? struct backing? { fields }`
+    Struct {
+        packed: bool,
+        backing: Option<Expr<'a>>,
+        fields: Vec<Field<'a>>,
+    },
+    /// `union { fields }`
+    Union { fields: Vec<Field<'a>> },
+    /// `enum backing? { variants }`
+    Enum {
+        backing: Option<Expr<'a>>,
+        variants: Vec<Variant<'a>>,
+    },
+    /// `fn(params) >- ret -> body;` or `fn(params) >- ret { body }`
+    Fn {
+        params: Vec<Field<'a>>,
+        ret: Option<Expr<'a>>,
+        body: Expr<'a>,
+    },
+    /// `expr;`
+    Const(Expr<'a>),
+}
+
+/// `name : ty`, used for struct fields and function parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    pub ty: Expr<'a>,
+}
+
+/// `name (: ty)? (= value)?`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant<'a> {
+    pub name: &'a str,
+    pub ty: Option<Expr<'a>>,
+    pub value: Option<Expr<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt<'a> {
+    Item(Item<'a>),
+    /// An expression without a trailing semicolon, either because it is
+    /// block-like or because it is the last one in its block.
+    Expr(Expr<'a>),
+    /// `expr;`
+    Semi(Expr<'a>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParserConfig {
+    /// Whether `if` expressions must use `then` before their first branch.
+    /// When `false`, `if cond { ... }` is accepted as well.
+    pub require_then: bool,
+    /// Whether a line break ends a statement like `;` does. Line breaks inside
+    /// `()` and `[]` never do, so expressions can still span several lines
+    /// there.
+    pub newline_terminates: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            require_then: true,
+            newline_terminates: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: &'static str,
+        /// The token type that was expected, if it was a single one
+        expected_ty: Option<TokenType>,
+        found: TokenType,
+        line: usize,
+        col: usize,
+    },
+    UnexpectedEof {
+        expected: &'static str,
+        expected_ty: Option<TokenType>,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken {
+                expected,
+                found,
+                line,
+                col,
+                ..
+            } => write!(f, "{line}:{col}: Expected {expected}, found {found:?}"),
+            ParseError::UnexpectedEof { expected, .. } => {
+                write!(f, "Expected {expected}, found end of file")
+            }
+        }
+    }
+}
+
+pub fn parse<'a>(tokens: &Tokens<'a>) -> Result<Vec<Stmt<'a>>, ParseError> {
+    parse_with_config(tokens, ParserConfig::default())
+}
+
+pub fn parse_with_config<'a>(
+    tokens: &Tokens<'a>,
+    config: ParserConfig,
+) -> Result<Vec<Stmt<'a>>, ParseError> {
+    let mut parser = Parser::new(tokens, config);
+    let mut stmts = Vec::new();
+
+    while parser.cursor.peek_type().is_some() {
+        stmts.push(parser.stmt()?);
+    }
+
+    Ok(stmts)
+}
+
+/// Like [`parse`], but instead of stopping at the first error, skips to the
+/// end of the broken statement and keeps going. Returns every statement that
+/// could be parsed along with all errors.
+pub fn parse_recovering<'a>(tokens: &Tokens<'a>) -> (Vec<Stmt<'a>>, Vec<ParseError>) {
+    let mut parser = Parser::new(tokens, ParserConfig::default());
+    parser.recover = true;
+    let mut stmts = Vec::new();
+
+    while parser.cursor.peek_type().is_some() {
+        let start = parser.cursor.pos();
+        match parser.stmt() {
+            Ok(stmt) => stmts.push(stmt),
+            Err(err) => {
+                parser.errors.push(err);
+                parser.synchronize();
+                // a stray `}` has no block to end, so skip it to make progress
+                if parser.cursor.pos() == start {
+                    parser.cursor.advance();
+                }
+            }
+        }
+    }
+
+    (stmts, parser.errors)
+}
+
+/// Parses a single expression spanning all of the tokens.
+pub fn parse_expr<'a>(tokens: &Tokens<'a>) -> Result<Expr<'a>, ParseError> {
+    let mut parser = Parser::new(tokens, ParserConfig::default());
+    let expr = parser.expr()?;
+    parser.expect_eof()?;
+    Ok(expr)
+}
+
+/// Position in a list of tokens, with the lookahead and matching a parser
+/// needs.
+#[derive(Debug, Clone)]
+pub struct TokenCursor<'t, 'a> {
+    tokens: &'t Tokens<'a>,
+    pos: usize,
+}
+
+impl<'t, 'a> TokenCursor<'t, 'a> {
+    pub fn new(tokens: &'t Tokens<'a>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    /// Index of the next token.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn tokens(&self) -> &'t Tokens<'a> {
+        self.tokens
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<&'t TokenSpan<'a>> {
+        self.tokens.spans.get(self.pos)
+    }
+
+    #[inline]
+    pub fn peek_type(&self) -> Option<TokenType> {
+        self.peek_nth_type(0)
+    }
+
+    #[inline]
+    pub fn peek_nth_type(&self, n: usize) -> Option<TokenType> {
+        self.tokens.types.get(self.pos + n).copied()
+    }
+
+    /// The token before the next one, if any.
+    pub fn prev(&self) -> Option<&'t TokenSpan<'a>> {
+        self.tokens.spans.get(self.pos.checked_sub(1)?)
+    }
+
+    /// Consumes the next token.
+    ///
+    /// # Panics
+    ///
+    /// If there are no tokens left; check with [`TokenCursor::peek`] first.
+    #[inline]
+    pub fn advance(&mut self) -> &'t TokenSpan<'a> {
+        let span = &self.tokens.spans[self.pos];
+        self.pos += 1;
+        span
+    }
+
+    /// Consumes the next token if it is of the given type.
+    pub fn eat(&mut self, ty: TokenType) -> bool {
+        if self.peek_type() == Some(ty) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the next token if it is of the given type, or fails with an
+    /// error carrying `ty` and saying `expected` was expected instead.
+    pub fn expect(
+        &mut self,
+        ty: TokenType,
+        expected: &'static str,
+    ) -> Result<&'t TokenSpan<'a>, ParseError> {
+        if self.peek_type() == Some(ty) {
+            Ok(self.advance())
+        } else {
+            Err(self.unexpected(expected, Some(ty)))
+        }
+    }
+
+    /// Builds an error for the token at the current position.
+    pub fn error(&self, expected: &'static str) -> ParseError {
+        self.unexpected(expected, None)
+    }
+
+    fn unexpected(&self, expected: &'static str, expected_ty: Option<TokenType>) -> ParseError {
+        match self.peek_type() {
+            Some(found) => {
+                let span = &self.tokens.spans[self.pos];
+                ParseError::UnexpectedToken {
+                    expected,
+                    expected_ty,
+                    found,
+                    line: span.line,
+                    col: span.col,
+                }
+            }
+            None => ParseError::UnexpectedEof {
+                expected,
+                expected_ty,
+            },
+        }
+    }
+}
+
+struct Parser<'t, 'a> {
+    cursor: TokenCursor<'t, 'a>,
+    config: ParserConfig,
+    /// How many `(` and `[` around the current position are still open,
+    /// counted from the innermost block
+    nesting: usize,
+    /// Whether blocks collect errors in `errors` and continue with their next
+    /// statement, instead of failing
+    recover: bool,
+    errors: Vec<ParseError>,
+}
+
+impl<'t, 'a> Parser<'t, 'a> {
+    fn new(tokens: &'t Tokens<'a>, config: ParserConfig) -> Self {
+        Self {
+            cursor: TokenCursor::new(tokens),
+            config,
+            nesting: 0,
+            recover: false,
+            errors: Vec::new(),
+        }
+    }
+
+    fn expect_eof(&self) -> Result<(), ParseError> {
+        match self.cursor.peek_type() {
+            None => Ok(()),
+            Some(_) => Err(self.cursor.error("end of file")),
+        }
+    }
+
+    /// Whether the next token starts a new line that ends the current
+    /// statement, see [`ParserConfig::newline_terminates`].
+    fn at_line_break(&self) -> bool {
+        if !self.config.newline_terminates || self.nesting > 0 {
+            return false;
+        }
+        let (Some(prev), Some(next)) = (self.cursor.prev(), self.cursor.peek()) else {
+            return false;
+        };
+
+        // strings can span several lines themselves
+        let prev_end_line = prev.line + prev.slice.matches('\n').count();
+        next.line > prev_end_line
+    }
+
+    /// Expects the `;` ending a statement, or a line break or the end of the
+    /// file if those end statements too.
+    fn expect_terminator(&mut self) -> Result<(), ParseError> {
+        let newline_ok = self.config.newline_terminates
+            && (self.at_line_break() || self.cursor.peek_type().is_none());
+        if self.cursor.eat(TokenType::Semi) || newline_ok {
+            Ok(())
+        } else {
+            Err(self.cursor.error("`;`"))
+        }
+    }
+
+    /// Skips the rest of a broken statement: up to and including the next `;`,
+    /// or up to the `}` closing the block it is in. Nested blocks are skipped
+    /// entirely.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+
+        while let Some(ty) = self.cursor.peek_type() {
+            match ty {
+                TokenType::Semi if depth == 0 => {
+                    self.cursor.advance();
+                    return;
+                }
+                TokenType::RBrace if depth == 0 => return,
+                TokenType::RBrace => depth -= 1,
+                TokenType::LBrace => depth += 1,
+                _ => {}
+            }
+            self.cursor.advance();
+        }
+    }
+
+    fn stmt(&mut self) -> Result<Stmt<'a>, ParseError> {
+        let is_item = self.cursor.peek_type() == Some(TokenType::Pub)
+            || (self.cursor.peek_type() == Some(TokenType::Ident)
+                && self.cursor.peek_nth_type(1) == Some(TokenType::Colon)
+                && self.cursor.peek_nth_type(2) == Some(TokenType::Colon));
+        if is_item {
+            return Ok(Stmt::Item(self.item()?));
+        }
+
+        let expr = self.expr()?;
+
+        if self.cursor.eat(TokenType::Semi) {
+            return Ok(Stmt::Semi(expr));
+        }
+
+        let is_block_like = matches!(expr, Expr::Block(_) | Expr::If { .. });
+        if is_block_like || matches!(self.cursor.peek_type(), None | Some(TokenType::RBrace)) {
+            Ok(Stmt::Expr(expr))
+        } else if self.at_line_break() {
+            Ok(Stmt::Semi(expr))
+        } else {
+            Err(self.cursor.error("`;`"))
+        }
+    }
+
+    /// Parses an item declaration. Its visibility comes before its name, so
+    /// `pub Flags :: packed struct` is accepted but `Flags :: pub struct` isn't.
+    fn item(&mut self) -> Result<Item<'a>, ParseError> {
+        let visibility = match self.cursor.eat(TokenType::Pub) {
+            true => Visibility::Public,
+            false => Visibility::Private,
+        };
+
+        let name = self.cursor.expect(TokenType::Ident, "item name")?.slice;
+        self.cursor.expect(TokenType::Colon, "`::`")?;
+        self.cursor.expect(TokenType::Colon, "`::`")?;
+
+        let kind = match self.cursor.peek_type() {
+            Some(TokenType::Packed | TokenType::Struct) => {
+                let packed = self.cursor.eat(TokenType::Packed);
+                self.cursor.expect(TokenType::Struct, "`struct`")?;
+                let backing = self.backing_type()?;
+                self.cursor.expect(TokenType::LBrace, "`{`")?;
+                let fields = self.comma_separated(TokenType::RBrace, "`}`", Self::field)?;
+                ItemKind::Struct {
+                    packed,
+                    backing,
+                    fields,
+                }
+            }
+            Some(TokenType::Union) => {
+                self.cursor.advance();
+                self.cursor.expect(TokenType::LBrace, "`{`")?;
+                let fields = self.comma_separated(TokenType::RBrace, "`}`", Self::field)?;
+                ItemKind::Union { fields }
+            }
+            Some(TokenType::Enum) => {
+                self.cursor.advance();
+                let backing = self.backing_type()?;
+                self.cursor.expect(TokenType::LBrace, "`{`")?;
+                let variants = self.comma_separated(TokenType::RBrace, "`}`", Self::variant)?;
+                ItemKind::Enum { backing, variants }
+            }
+            Some(TokenType::Fn) => {
+                self.cursor.advance();
+                self.cursor.expect(TokenType::LParens, "`(`")?;
+                let params = self.comma_separated(TokenType::RParens, "`)`", Self::field)?;
+
+                let ret = match self.cursor.eat(TokenType::Feather) {
+                    true => Some(self.expr()?),
+                    false => None,
+                };
+
+                let body = if self.cursor.eat(TokenType::Arrow) {
+                    let body = self.expr()?;
+                    self.expect_terminator()?;
+                    body
+                } else {
+                    self.cursor.expect(TokenType::LBrace, "`->` or `{`")?;
+                    self.block()?
+                };
+
+                ItemKind::Fn { params, ret, body }
+            }
+            _ => {
+                let value = self.expr()?;
+                self.expect_terminator()?;
+                ItemKind::Const(value)
+            }
+        };
+
+        Ok(Item {
+            visibility,
+            name,
+            kind,
+        })
+    }
+
+    /// Parses the optional backing type of a struct or enum, like the `u8` in
+    /// `enum u8 { ... }`.
+    fn backing_type(&mut self) -> Result<Option<Expr<'a>>, ParseError> {
+        match self.cursor.peek_type() {
+            Some(TokenType::LBrace) => Ok(None),
+            _ => Ok(Some(self.expr()?)),
+        }
+    }
+
+    fn field(&mut self) -> Result<Field<'a>, ParseError> {
+        let name = self.cursor.expect(TokenType::Ident, "field name")?.slice;
+        self.cursor.expect(TokenType::Colon, "`:`")?;
+        let ty = self.expr()?;
+        Ok(Field { name, ty })
+    }
+
+    fn variant(&mut self) -> Result<Variant<'a>, ParseError> {
+        let name = self.cursor.expect(TokenType::Ident, "variant name")?.slice;
+
+        let ty = match self.cursor.eat(TokenType::Colon) {
+            true => Some(self.expr()?),
+            false => None,
+        };
+        let value = match self.cursor.eat(TokenType::Equal) {
+            true => Some(self.expr()?),
+            false => None,
+        };
+
+        Ok(Variant { name, ty, value })
+    }
+
+    /// Parses the statements of a block, after its opening brace.
+    fn block(&mut self) -> Result<Expr<'a>, ParseError> {
+        let nesting = mem::take(&mut self.nesting);
+        let stmts = self.block_stmts();
+        self.nesting = nesting;
+        Ok(Expr::Block(stmts?))
+    }
+
+    fn block_stmts(&mut self) -> Result<Vec<Stmt<'a>>, ParseError> {
+        let mut stmts = Vec::new();
+
+        while !self.cursor.eat(TokenType::RBrace) {
+            if self.cursor.peek_type().is_none() {
+                return Err(self.cursor.error("`}`"));
+            }
+
+            match self.stmt() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) if self.recover => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(stmts)
+    }
+
+    /// Parses an if expression, after its `if` keyword.
+    fn if_expr(&mut self) -> Result<Expr<'a>, ParseError> {
+        let cond = self.expr()?;
+
+        let then = if self.cursor.eat(TokenType::Then) {
+            self.expr()?
+        } else if !self.config.require_then && self.cursor.eat(TokenType::LBrace) {
+            self.block()?
+        } else {
+            return Err(self.cursor.error("`then`"));
+        };
+
+        let else_ = if self.cursor.eat(TokenType::Else) {
+            Some(Box::new(self.expr()?))
+        } else {
+            None
+        };
+
+        Ok(Expr::If {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            else_,
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr<'a>, ParseError> {
+        self.expr_bp(0)
+    }
+
+    /// Pratt parser loop. Only operators binding at least as tightly as
+    /// `min_bp` are consumed.
+    fn expr_bp(&mut self, min_bp: u8) -> Result<Expr<'a>, ParseError> {
+        let mut lhs = match self.cursor.peek_type().and_then(prefix_binding_power) {
+            Some(r_bp) => {
+                let op = self.cursor.peek_type().unwrap();
+                self.cursor.advance();
+                let expr = Box::new(self.expr_bp(r_bp)?);
+                match op {
+                    TokenType::Incr => Expr::PreIncr(expr),
+                    TokenType::Decr => Expr::PreDecr(expr),
+                    _ => Expr::Unary { op, expr },
+                }
+            }
+            None => self.primary()?,
+        };
+
+        while let Some(op) = self.cursor.peek_type() {
+            if self.at_line_break() {
+                break;
+            }
+
+            if let Some(l_bp) = postfix_binding_power(op) {
+                if l_bp < min_bp {
+                    break;
+                }
+
+                lhs = self.postfix(op, lhs)?;
+                continue;
+            }
+
+            // `cond ? a : b` binds looser than any other operator, and is right
+            // associative: `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+            if op == TokenType::Question {
+                if min_bp > 0 {
+                    break;
+                }
+
+                self.cursor.advance();
+                let then = self.expr()?;
+                self.cursor.expect(TokenType::Colon, "`:`")?;
+                let else_ = self.expr()?;
+                lhs = Expr::Ternary {
+                    cond: Box::new(lhs),
+                    then: Box::new(then),
+                    else_: Box::new(else_),
+                };
+                continue;
+            }
+
+            if let Some((l_bp, r_bp)) = infix_binding_power(op) {
+                if l_bp < min_bp {
+                    break;
+                }
+
+                self.cursor.advance();
+                let rhs = self.expr_bp(r_bp)?;
+                lhs = Expr::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                };
+                continue;
+            }
+
+            break;
+        }
+
+        Ok(lhs)
+    }
+
+    fn postfix(&mut self, op: TokenType, lhs: Expr<'a>) -> Result<Expr<'a>, ParseError> {
+        self.cursor.advance();
+
+        match op {
+            TokenType::LBracket => {
+                let index = self.nested(|p| {
+                    let index = p.expr()?;
+                    p.cursor.expect(TokenType::RBracket, "`]`")?;
+                    Ok(index)
+                })?;
+                Ok(Expr::Index {
+                    base: Box::new(lhs),
+                    index: Box::new(index),
+                })
+            }
+            TokenType::Dot => {
+                let field = self.cursor.expect(TokenType::Ident, "field name")?.slice;
+                Ok(Expr::Field {
+                    base: Box::new(lhs),
+                    field,
+                })
+            }
+            TokenType::LParens => {
+                let args = self.nested(|p| p.list(TokenType::RParens, "`)`"))?;
+                Ok(Expr::Call {
+                    callee: Box::new(lhs),
+                    args,
+                })
+            }
+            TokenType::Incr => Ok(Expr::PostIncr(Box::new(lhs))),
+            TokenType::Decr => Ok(Expr::PostDecr(Box::new(lhs))),
+            _ => unreachable!("{op:?} is not a postfix operator"),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr<'a>, ParseError> {
+        let Some(ty) = self.cursor.peek_type() else {
+            return Err(self.cursor.error("expression"));
+        };
+
+        match ty {
+            TokenType::Ident => Ok(Expr::Ident(self.cursor.advance().slice)),
+            TokenType::Num => Ok(Expr::Num(self.cursor.advance().slice)),
+            TokenType::String => Ok(Expr::String(self.cursor.advance().slice)),
+            TokenType::Char => Ok(Expr::Char(self.cursor.advance().slice)),
+            TokenType::LParens => {
+                self.cursor.advance();
+                self.nested(|p| {
+                    let expr = p.expr()?;
+                    p.cursor.expect(TokenType::RParens, "`)`")?;
+                    Ok(expr)
+                })
+            }
+            TokenType::LBracket => {
+                self.cursor.advance();
+                let items = self.nested(|p| p.list(TokenType::RBracket, "`]`"))?;
+                Ok(Expr::Array(items))
+            }
+            TokenType::LBrace => {
+                self.cursor.advance();
+                self.block()
+            }
+            TokenType::If => {
+                self.cursor.advance();
+                self.if_expr()
+            }
+            TokenType::Do => {
+                self.cursor.advance();
+                let body = self.expr()?;
+                self.cursor.expect(TokenType::While, "`while`")?;
+                let cond = self.expr()?;
+                Ok(Expr::DoWhile {
+                    body: Box::new(body),
+                    cond: Box::new(cond),
+                })
+            }
+            _ => Err(self.cursor.error("expression")),
+        }
+    }
+
+    /// Runs `parse` inside a `(` or `[`, where line breaks don't end
+    /// statements.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// Parses comma-separated expressions up to and including the closing token.
+    /// A trailing comma is allowed.
+    fn list(
+        &mut self,
+        close: TokenType,
+        expected: &'static str,
+    ) -> Result<Vec<Expr<'a>>, ParseError> {
+        self.comma_separated(close, expected, Self::expr)
+    }
+
+    /// Parses comma-separated elements up to and including the closing token.
+    /// A trailing comma is allowed.
+    fn comma_separated<T>(
+        &mut self,
+        close: TokenType,
+        expected: &'static str,
+        mut element: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+
+        while !self.cursor.eat(close) {
+            items.push(element(self)?);
+
+            if !self.cursor.eat(TokenType::Comma) {
+                self.cursor.expect(close, expected)?;
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+}
+
+fn prefix_binding_power(ty: TokenType) -> Option<u8> {
+    match ty {
+        // `not a == b` negates the whole comparison
+        TokenType::Not => Some(9),
+        TokenType::Minus | TokenType::Tilde | TokenType::Incr | TokenType::Decr => Some(23),
+        _ => None,
+    }
+}
+
+fn infix_binding_power(ty: TokenType) -> Option<(u8, u8)> {
+    let bp = match ty {
+        TokenType::Or => (1, 2),
+        TokenType::Xor => (3, 4),
+        TokenType::And => (5, 6),
+        TokenType::Equals
+        | TokenType::NotEquals
+        | TokenType::LessThan
+        | TokenType::GreaterThan
+        | TokenType::LessEqual
+        | TokenType::GreaterEqual => (9, 10),
+        TokenType::Pipe => (11, 12),
+        TokenType::Caret => (13, 14),
+        TokenType::Ampersand => (15, 16),
+        TokenType::LShift | TokenType::RShift => (17, 18),
+        TokenType::Plus | TokenType::Minus => (19, 20),
+        TokenType::Mul | TokenType::Div | TokenType::Modulo => (21, 22),
+        // right associative
+        TokenType::Pow => (26, 25),
+        _ => return None,
+    };
+
+    Some(bp)
+}
+
+// The lexer always takes the longest operator, so `a+++b` is `a++ + b` and
+// `a+++++b` is `a++ ++ + b`, which doesn't parse. Write `a + ++b` for the
+// other meaning.
+fn postfix_binding_power(ty: TokenType) -> Option<u8> {
+    match ty {
+        TokenType::LBracket
+        | TokenType::LParens
+        | TokenType::Dot
+        | TokenType::Incr
+        | TokenType::Decr => Some(27),
+        _ => None,
+    }
+}
+
+/// Boxed identifier expression, for the expected trees in tests.
+#[cfg(test)]
+fn ident(name: &str) -> Box<Expr<'_>> {
+    Box::new(Expr::Ident(name))
+}
+
+#[cfg(test)]
+mod tests_index {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ident, parse_expr, Expr, ParseError},
+    };
+
+    #[test]
+    fn index() {
+        let tokens = lex("test", "a[i]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Index {
+                base: ident("a"),
+                index: ident("i"),
+            })
+        );
+    }
+
+    #[test]
+    fn nested_index() {
+        let tokens = lex("test", "a[i][j]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Index {
+                base: Box::new(Expr::Index {
+                    base: ident("a"),
+                    index: ident("i"),
+                }),
+                index: ident("j"),
+            })
+        );
+    }
+
+    #[test]
+    fn array() {
+        let tokens = lex("test", "[1, 2, 3]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Array(vec![
+                Expr::Num("1"),
+                Expr::Num("2"),
+                Expr::Num("3")
+            ]))
+        );
+    }
+
+    #[test]
+    fn empty_array() {
+        let tokens = lex("test", "[]");
+        assert_eq!(parse_expr(&tokens), Ok(Expr::Array(vec![])));
+    }
+
+    #[test]
+    fn trailing_comma() {
+        let tokens = lex("test", "[1, 2,]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Array(vec![Expr::Num("1"), Expr::Num("2")]))
+        );
+    }
+
+    #[test]
+    fn unclosed_bracket() {
+        let tokens = lex("test", "a[i;");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`]`",
+                expected_ty: Some(TokenType::RBracket),
+                found: TokenType::Semi,
+                line: 1,
+                col: 3,
+            })
+        );
+
+        let tokens = lex("test", "[[1, 2]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedEof {
+                expected: "`]`",
+                expected_ty: Some(TokenType::RBracket)
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_call {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ident, parse_expr, Expr, ParseError},
+    };
+
+    #[test]
+    fn call() {
+        let tokens = lex("test", "f(a, b)");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Call {
+                callee: ident("f"),
+                args: vec![Expr::Ident("a"), Expr::Ident("b")],
+            })
+        );
+    }
+
+    #[test]
+    fn no_args() {
+        let tokens = lex("test", "f()");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Call {
+                callee: ident("f"),
+                args: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn nested_call() {
+        let tokens = lex("test", "f(g(x))");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Call {
+                callee: ident("f"),
+                args: vec![Expr::Call {
+                    callee: ident("g"),
+                    args: vec![Expr::Ident("x")],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn trailing_comma() {
+        let tokens = lex("test", "f(a, b,)");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Call {
+                callee: ident("f"),
+                args: vec![Expr::Ident("a"), Expr::Ident("b")],
+            })
+        );
+    }
+
+    #[test]
+    fn binds_tighter_than_binary() {
+        let tokens = lex("test", "a + f(b)");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Binary {
+                op: TokenType::Plus,
+                lhs: ident("a"),
+                rhs: Box::new(Expr::Call {
+                    callee: ident("f"),
+                    args: vec![Expr::Ident("b")],
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_paren() {
+        let tokens = lex("test", "f(a, b;");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`)`",
+                expected_ty: Some(TokenType::RParens),
+                found: TokenType::Semi,
+                line: 1,
+                col: 6,
+            })
+        );
+
+        let tokens = lex("test", "f(g(x)");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedEof {
+                expected: "`)`",
+                expected_ty: Some(TokenType::RParens)
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_field {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ident, parse_expr, Expr},
+    };
+
+    #[test]
+    fn chained() {
+        let tokens = lex("test", "a.b.c\n");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Field {
+                base: Box::new(Expr::Field {
+                    base: ident("a"),
+                    field: "b",
+                }),
+                field: "c",
+            })
+        );
+    }
+
+    #[test]
+    fn method_call() {
+        let tokens = lex("test", "a.b(c)");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Call {
+                callee: Box::new(Expr::Field {
+                    base: ident("a"),
+                    field: "b",
+                }),
+                args: vec![Expr::Ident("c")],
+            })
+        );
+    }
+
+    #[test]
+    fn float_is_not_field() {
+        let tokens = lex("test", "1.5\n");
+        assert_eq!(tokens.types.as_slice(), &[TokenType::Num]);
+        assert_eq!(tokens.spans[0].slice, "1.5");
+        assert_eq!(parse_expr(&tokens), Ok(Expr::Num("1.5")));
+
+        let tokens = lex("test", "a.b[1.5]");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Index {
+                base: Box::new(Expr::Field {
+                    base: ident("a"),
+                    field: "b",
+                }),
+                index: Box::new(Expr::Num("1.5")),
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_if {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{parse, parse_with_config, Expr, ParseError, ParserConfig, Stmt},
+    };
+
+    fn empty_if(cond: &str) -> Vec<Stmt<'_>> {
+        vec![Stmt::Expr(Expr::If {
+            cond: Box::new(Expr::Ident(cond)),
+            then: Box::new(Expr::Block(vec![])),
+            else_: None,
+        })]
+    }
+
+    #[test]
+    fn with_then() {
+        let tokens = lex("test", "if x then { }");
+        assert_eq!(parse(&tokens), Ok(empty_if("x")));
+
+        let config = ParserConfig {
+            require_then: false,
+            ..Default::default()
+        };
+        assert_eq!(parse_with_config(&tokens, config), Ok(empty_if("x")));
+    }
+
+    #[test]
+    fn without_then() {
+        let tokens = lex("test", "if x { }");
+        let config = ParserConfig {
+            require_then: false,
+            ..Default::default()
+        };
+        assert_eq!(parse_with_config(&tokens, config), Ok(empty_if("x")));
+    }
+
+    #[test]
+    fn missing_then() {
+        let tokens = lex("test", "if x { }");
+        assert_eq!(
+            parse(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`then`",
+                expected_ty: None,
+                found: TokenType::LBrace,
+                line: 1,
+                col: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn else_branch() {
+        let tokens = lex("test", "if x then a else b;");
+        assert_eq!(
+            parse(&tokens),
+            Ok(vec![Stmt::Semi(Expr::If {
+                cond: Box::new(Expr::Ident("x")),
+                then: Box::new(Expr::Ident("a")),
+                else_: Some(Box::new(Expr::Ident("b"))),
+            })])
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_visibility {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{parse, Expr, Field, Item, ItemKind, ParseError, Stmt, Visibility},
+    };
+
+    fn item<'a>(stmts: Vec<Stmt<'a>>) -> Item<'a> {
+        match stmts.as_slice() {
+            [Stmt::Item(item)] => item.clone(),
+            _ => panic!("Expected a single item, got {stmts:?}"),
+        }
+    }
+
+    #[test]
+    fn pub_fn() {
+        let tokens = lex("test", "pub add :: fn(a : i32, b : i32) >- i32 -> a + b;");
+        let item = item(parse(&tokens).unwrap());
+        assert_eq!(item.visibility, Visibility::Public);
+        assert_eq!(item.name, "add");
+        assert!(matches!(item.kind, ItemKind::Fn { .. }));
+    }
+
+    #[test]
+    fn pub_struct() {
+        let tokens = lex("test", "pub Point :: struct { x : i32, y : i32 }");
+        assert_eq!(
+            item(parse(&tokens).unwrap()),
+            Item {
+                visibility: Visibility::Public,
+                name: "Point",
+                kind: ItemKind::Struct {
+                    packed: false,
+                    backing: None,
+                    fields: vec![
+                        Field {
+                            name: "x",
+                            ty: Expr::Ident("i32"),
+                        },
+                        Field {
+                            name: "y",
+                            ty: Expr::Ident("i32"),
+                        },
+                    ],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn pub_packed_struct() {
+        let tokens = lex("test", "pub Flags :: packed struct u32 { a : bool, }");
+        let item = item(parse(&tokens).unwrap());
+        assert_eq!(item.visibility, Visibility::Public);
+        assert!(matches!(
+            item.kind,
+            ItemKind::Struct {
+                packed: true,
+                backing: Some(Expr::Ident("u32")),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn private_by_default() {
+        let tokens = lex("test", "Point :: struct {}");
+        let item = item(parse(&tokens).unwrap());
+        assert_eq!(item.visibility, Visibility::Private);
+    }
+
+    #[test]
+    fn pub_after_name() {
+        let tokens = lex("test", "Point :: pub struct {}");
+        assert_eq!(
+            parse(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "expression",
+                expected_ty: None,
+                found: TokenType::Pub,
+                line: 1,
+                col: 9,
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_incr_decr {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ident, parse_expr, Expr},
+    };
+
+    #[test]
+    fn prefix() {
+        let tokens = lex("test", "++x");
+        assert_eq!(parse_expr(&tokens), Ok(Expr::PreIncr(ident("x"))));
+
+        // binds looser than member access
+        let tokens = lex("test", "--x.y");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::PreDecr(Box::new(Expr::Field {
+                base: ident("x"),
+                field: "y",
+            })))
+        );
+    }
+
+    #[test]
+    fn postfix() {
+        let tokens = lex("test", "x++");
+        assert_eq!(parse_expr(&tokens), Ok(Expr::PostIncr(ident("x"))));
+
+        let tokens = lex("test", "-f()--");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Unary {
+                op: TokenType::Minus,
+                expr: Box::new(Expr::PostDecr(Box::new(Expr::Call {
+                    callee: ident("f"),
+                    args: vec![],
+                }))),
+            })
+        );
+    }
+
+    #[test]
+    fn max_munch() {
+        let expected = Ok(Expr::Binary {
+            op: TokenType::Plus,
+            lhs: Box::new(Expr::PostIncr(ident("a"))),
+            rhs: ident("b"),
+        });
+
+        assert_eq!(parse_expr(&lex("test", "a++ + b")), expected);
+        assert_eq!(parse_expr(&lex("test", "a+++b")), expected);
+        assert_eq!(
+            parse_expr(&lex("test", "a + ++b")),
+            Ok(Expr::Binary {
+                op: TokenType::Plus,
+                lhs: ident("a"),
+                rhs: Box::new(Expr::PreIncr(ident("b"))),
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_recovery {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{parse_recovering, Expr, ParseError, Stmt},
+    };
+
+    #[test]
+    fn block() {
+        let tokens = lex("test", "{ x := ; y; a b { c; } d; }");
+        let (stmts, errors) = parse_recovering(&tokens);
+
+        assert_eq!(
+            stmts,
+            [Stmt::Expr(Expr::Block(vec![Stmt::Semi(Expr::Ident("y"))]))]
+        );
+        assert_eq!(
+            errors,
+            [
+                ParseError::UnexpectedToken {
+                    expected: "`;`",
+                    expected_ty: None,
+                    found: TokenType::Colon,
+                    line: 1,
+                    col: 4,
+                },
+                ParseError::UnexpectedToken {
+                    expected: "`;`",
+                    expected_ty: None,
+                    found: TokenType::Ident,
+                    line: 1,
+                    col: 14,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn top_level() {
+        let tokens = lex("test", "a b; } c;");
+        let (stmts, errors) = parse_recovering(&tokens);
+
+        assert_eq!(stmts, [Stmt::Semi(Expr::Ident("c"))]);
+        assert_eq!(errors.len(), 2);
+    }
+}
+
+#[cfg(test)]
+mod tests_ternary {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ident, parse_expr, Expr, ParseError},
+    };
+
+    #[test]
+    fn simple() {
+        let tokens = lex("test", "a or b ? x + 1 : y");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Ternary {
+                cond: Box::new(Expr::Binary {
+                    op: TokenType::Or,
+                    lhs: ident("a"),
+                    rhs: ident("b"),
+                }),
+                then: Box::new(Expr::Binary {
+                    op: TokenType::Plus,
+                    lhs: ident("x"),
+                    rhs: Box::new(Expr::Num("1")),
+                }),
+                else_: ident("y"),
+            })
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let tokens = lex("test", "a ? b ? c : d : e ? f : g");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::Ternary {
+                cond: ident("a"),
+                then: Box::new(Expr::Ternary {
+                    cond: ident("b"),
+                    then: ident("c"),
+                    else_: ident("d"),
+                }),
+                else_: Box::new(Expr::Ternary {
+                    cond: ident("e"),
+                    then: ident("f"),
+                    else_: ident("g"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_colon() {
+        let tokens = lex("test", "a ? b c");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`:`",
+                expected_ty: Some(TokenType::Colon),
+                found: TokenType::Ident,
+                line: 1,
+                col: 6,
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_precedence {
+    use crate::{lexer::lex, parser::infix_binding_power};
+
+    #[test]
+    fn matches_binding_power() {
+        let tokens = lex(
+            "test",
+            "or xor and == != < > <= >= | ^ & << >> + - * / % ** not ~ ++ -- = . ? x",
+        );
+        for &a in tokens.types.iter() {
+            let bp_a = infix_binding_power(a).map(|(l, r)| l.min(r));
+            assert_eq!(a.precedence().is_some(), bp_a.is_some(), "{a:?}");
+
+            for &b in tokens.types.iter() {
+                let bp_b = infix_binding_power(b).map(|(l, r)| l.min(r));
+                assert_eq!(
+                    a.precedence().cmp(&b.precedence()),
+                    bp_a.cmp(&bp_b),
+                    "{a:?} {b:?}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_newline_terminates {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{parse, parse_with_config, Expr, ItemKind, ParseError, ParserConfig, Stmt},
+    };
+
+    const CONFIG: ParserConfig = ParserConfig {
+        require_then: true,
+        newline_terminates: true,
+    };
+
+    fn binary<'a>(op: TokenType, lhs: Expr<'a>, rhs: Expr<'a>) -> Expr<'a> {
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    #[test]
+    fn two_statements() {
+        let tokens = lex("test", "f(x)\ny * 2\n");
+        let stmts = parse_with_config(&tokens, CONFIG).unwrap();
+        assert_eq!(
+            stmts,
+            [
+                Stmt::Semi(Expr::Call {
+                    callee: Box::new(Expr::Ident("f")),
+                    args: vec![Expr::Ident("x")],
+                }),
+                Stmt::Expr(binary(TokenType::Mul, Expr::Ident("y"), Expr::Num("2"))),
+            ]
+        );
+
+        // without the option the line break means nothing
+        assert_eq!(
+            parse(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`;`",
+                expected_ty: None,
+                found: TokenType::Ident,
+                line: 2,
+                col: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_line_expression() {
+        let tokens = lex("test", "x * (a\n    + b)\nf(a,\n  b)\n");
+        let stmts = parse_with_config(&tokens, CONFIG).unwrap();
+        assert_eq!(
+            stmts,
+            [
+                Stmt::Semi(binary(
+                    TokenType::Mul,
+                    Expr::Ident("x"),
+                    binary(TokenType::Plus, Expr::Ident("a"), Expr::Ident("b")),
+                )),
+                Stmt::Expr(Expr::Call {
+                    callee: Box::new(Expr::Ident("f")),
+                    args: vec![Expr::Ident("a"), Expr::Ident("b")],
+                }),
+            ]
+        );
+
+        // outside of parens, the operator starts a new statement
+        let tokens = lex("test", "a\n-b");
+        let stmts = parse_with_config(&tokens, CONFIG).unwrap();
+        assert_eq!(stmts.len(), 2);
+    }
+
+    #[test]
+    fn blocks_and_items() {
+        let code = "N :: 5\nmain :: fn() {\n    g(N)\n    h()\n}\n";
+        let tokens = lex("test", code);
+        let stmts = parse_with_config(&tokens, CONFIG).unwrap();
+        let [Stmt::Item(n), Stmt::Item(main)] = &stmts[..] else {
+            panic!("{stmts:?}");
+        };
+        assert_eq!(n.kind, ItemKind::Const(Expr::Num("5")));
+
+        let ItemKind::Fn {
+            body: Expr::Block(body),
+            ..
+        } = &main.kind
+        else {
+            panic!("{main:?}");
+        };
+        assert!(matches!(body[..], [Stmt::Semi(_), Stmt::Expr(_)]));
+    }
+
+    #[test]
+    fn line_break_inside_block_in_parens() {
+        let tokens = lex("test", "f({\n    a\n    b\n})");
+        let stmts = parse_with_config(&tokens, CONFIG).unwrap();
+        assert_eq!(
+            stmts,
+            [Stmt::Expr(Expr::Call {
+                callee: Box::new(Expr::Ident("f")),
+                args: vec![Expr::Block(vec![
+                    Stmt::Semi(Expr::Ident("a")),
+                    Stmt::Expr(Expr::Ident("b")),
+                ])],
+            })]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_do_while {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{parse, parse_expr, Expr, ParseError, Stmt},
+    };
+
+    #[test]
+    fn block_body() {
+        let tokens = lex("test", "do { f(); } while x < 10;");
+        assert_eq!(
+            parse(&tokens),
+            Ok(vec![Stmt::Semi(Expr::DoWhile {
+                body: Box::new(Expr::Block(vec![Stmt::Semi(Expr::Call {
+                    callee: Box::new(Expr::Ident("f")),
+                    args: vec![],
+                })])),
+                cond: Box::new(Expr::Binary {
+                    op: TokenType::LessThan,
+                    lhs: Box::new(Expr::Ident("x")),
+                    rhs: Box::new(Expr::Num("10")),
+                }),
+            })])
+        );
+    }
+
+    #[test]
+    fn expr_body() {
+        let tokens = lex("test", "do i++ while i");
+        assert_eq!(
+            parse_expr(&tokens),
+            Ok(Expr::DoWhile {
+                body: Box::new(Expr::PostIncr(Box::new(Expr::Ident("i")))),
+                cond: Box::new(Expr::Ident("i")),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_while() {
+        let tokens = lex("test", "do { } x");
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(ParseError::UnexpectedToken {
+                expected: "`while`",
+                expected_ty: Some(TokenType::While),
+                found: TokenType::Ident,
+                line: 1,
+                col: 7,
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_token_cursor {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{ParseError, TokenCursor},
+    };
+
+    #[test]
+    fn eat() {
+        let tokens = lex("test", "a + b");
+        let mut cursor = TokenCursor::new(&tokens);
+
+        assert!(!cursor.eat(TokenType::Plus));
+        assert_eq!(cursor.pos(), 0);
+        assert!(cursor.eat(TokenType::Ident));
+        assert_eq!(cursor.pos(), 1);
+        assert_eq!(cursor.prev().unwrap().slice, "a");
+        assert_eq!(cursor.peek().unwrap().slice, "+");
+        assert_eq!(cursor.peek_nth_type(1), Some(TokenType::Ident));
+    }
+
+    #[test]
+    fn expect() {
+        let tokens = lex("test", "x;\n)");
+        let mut cursor = TokenCursor::new(&tokens);
+
+        assert_eq!(cursor.expect(TokenType::Ident, "name").unwrap().slice, "x");
+        assert_eq!(cursor.expect(TokenType::Semi, "`;`").unwrap().slice, ";");
+
+        // failing doesn't consume anything
+        let err = ParseError::UnexpectedToken {
+            expected: "`;`",
+            expected_ty: Some(TokenType::Semi),
+            found: TokenType::RParens,
+            line: 2,
+            col: 0,
+        };
+        assert_eq!(cursor.expect(TokenType::Semi, "`;`").unwrap_err(), err);
+        assert_eq!(cursor.peek_type(), Some(TokenType::RParens));
+
+        cursor.advance();
+        assert_eq!(
+            cursor.expect(TokenType::Semi, "`;`").unwrap_err(),
+            ParseError::UnexpectedEof {
+                expected: "`;`",
+                expected_ty: Some(TokenType::Semi)
+            }
+        );
+    }
+}
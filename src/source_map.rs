@@ -0,0 +1,142 @@
+//! Global byte offsets spanning several files.
+//!
+//! Every file gets a range of global offsets right after the previous file's,
+//! so a single offset identifies both a file and a position in it.
+
+use crate::lexer::{lex, Tokens};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(pub usize);
+
+#[derive(Debug)]
+struct SourceFile {
+    name: String,
+    /// Global offset of the file's first byte
+    base: usize,
+    len: usize,
+    /// Sorted local offsets of the file's line breaks, filled in by
+    /// [`lex_into`]
+    line_breaks: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a file of `len` bytes. Its global offsets start right after
+    /// the previous file's end-of-file offset.
+    pub fn add_file(&mut self, name: &str, len: usize) -> FileId {
+        let base = self.files.last().map_or(0, |file| file.base + file.len + 1);
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            base,
+            len,
+            line_breaks: Vec::new(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.0].name
+    }
+
+    /// Global offset of the first byte of a file.
+    pub fn base_offset(&self, file: FileId) -> usize {
+        self.files[file.0].base
+    }
+
+    /// File, line and column of a global offset. Lines start at 1, columns at
+    /// 0, like in [`TokenSpan`](crate::lexer::TokenSpan).
+    pub fn locate(&self, global_offset: usize) -> Option<(FileId, usize, usize)> {
+        let idx = self
+            .files
+            .partition_point(|file| file.base <= global_offset)
+            .checked_sub(1)?;
+        let file = &self.files[idx];
+
+        // the end-of-file offset is still part of the file
+        let offset = global_offset - file.base;
+        if offset > file.len {
+            return None;
+        }
+
+        let line = file.line_breaks.partition_point(|&brk| brk < offset);
+        let line_start = match line {
+            0 => 0,
+            _ => file.line_breaks[line - 1] + 1,
+        };
+        Some((FileId(idx), line + 1, offset - line_start))
+    }
+}
+
+/// Lexes a file registered in `map`, recording its line breaks there so
+/// global offsets in it can be located.
+pub fn lex_into<'a>(map: &mut SourceMap, file: FileId, code: &'a str) -> Tokens<'a> {
+    let source = &mut map.files[file.0];
+    assert_eq!(
+        source.len,
+        code.len(),
+        "{} was registered with a different length",
+        source.name
+    );
+
+    let tokens = lex(&source.name, code);
+    source.line_breaks = tokens.line_breaks.as_slice().to_vec();
+    tokens
+}
+
+#[cfg(test)]
+mod tests_locate {
+    use crate::source_map::{lex_into, SourceMap};
+
+    #[test]
+    fn two_files() {
+        let a = "a;\nb;\n";
+        let b = "x :: 1;\n\ny;";
+
+        let mut map = SourceMap::new();
+        let a_id = map.add_file("a.sus", a.len());
+        let b_id = map.add_file("b.sus", b.len());
+        lex_into(&mut map, a_id, a);
+        let tokens = lex_into(&mut map, b_id, b);
+
+        assert_eq!(map.base_offset(a_id), 0);
+        assert_eq!(map.base_offset(b_id), a.len() + 1);
+        assert_eq!(map.name(b_id), "b.sus");
+
+        assert_eq!(map.locate(0), Some((a_id, 1, 0)));
+        assert_eq!(map.locate(4), Some((a_id, 2, 1)));
+        assert_eq!(map.locate(a.len()), Some((a_id, 3, 0)));
+
+        let y = tokens.spans[tokens.types.len() - 2].offset;
+        assert_eq!(map.locate(map.base_offset(b_id) + y), Some((b_id, 3, 0)));
+        assert_eq!(map.locate(map.base_offset(b_id) + 2), Some((b_id, 1, 2)));
+        assert_eq!(map.locate(map.base_offset(b_id) + b.len() + 1), None);
+    }
+
+    #[test]
+    fn many_lines() {
+        // lines are found by binary search, so this doesn't scan 50k line breaks
+        // for every lookup
+        let code: String = (0..50_000).map(|line| format!("x{line};\n")).collect();
+
+        let mut map = SourceMap::new();
+        let file = map.add_file("big.sus", code.len());
+        lex_into(&mut map, file, &code);
+
+        let middle = code.find("x25000;").unwrap();
+        assert_eq!(map.locate(middle), Some((file, 25_001, 0)));
+        assert_eq!(map.locate(middle + 3), Some((file, 25_001, 3)));
+        for line in (0..50_000).step_by(997) {
+            let offset = code.find(&format!("\nx{line};")).map_or(0, |brk| brk + 1);
+            assert_eq!(map.locate(offset + 1), Some((file, line + 1, 1)));
+        }
+        assert_eq!(map.locate(code.len()), Some((file, 50_001, 0)));
+    }
+}
@@ -0,0 +1,166 @@
+//! Walking the AST.
+//!
+//! Every `visit_*` method of [`Visitor`] recurses into its node's children by
+//! default, via the matching `walk_*` function. Overriding a method and
+//! calling `walk_*` from it keeps the recursion going; not calling it skips
+//! the node's children.
+
+use crate::parser::{Expr, Item, ItemKind, Stmt};
+
+pub trait Visitor<'a> {
+    fn visit_expr(&mut self, expr: &Expr<'a>) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'a>) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_item(&mut self, item: &Item<'a>) {
+        walk_item(self, item);
+    }
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &Expr<'a>) {
+    match expr {
+        Expr::Ident(_) | Expr::Num(_) | Expr::String(_) | Expr::Char(_) => {}
+        Expr::Array(items) => {
+            for item in items {
+                visitor.visit_expr(item);
+            }
+        }
+        Expr::Index { base, index } => {
+            visitor.visit_expr(base);
+            visitor.visit_expr(index);
+        }
+        Expr::Field { base, .. } => visitor.visit_expr(base),
+        Expr::Call { callee, args } => {
+            visitor.visit_expr(callee);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        Expr::PreIncr(expr)
+        | Expr::PreDecr(expr)
+        | Expr::PostIncr(expr)
+        | Expr::PostDecr(expr)
+        | Expr::Unary { expr, .. } => visitor.visit_expr(expr),
+        Expr::Binary { lhs, rhs, .. } => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Expr::Block(stmts) => {
+            for stmt in stmts {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Expr::Ternary { cond, then, else_ } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then);
+            visitor.visit_expr(else_);
+        }
+        Expr::If { cond, then, else_ } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then);
+            if let Some(else_) = else_ {
+                visitor.visit_expr(else_);
+            }
+        }
+        Expr::DoWhile { body, cond } => {
+            visitor.visit_expr(body);
+            visitor.visit_expr(cond);
+        }
+    }
+}
+
+pub fn walk_stmt<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, stmt: &Stmt<'a>) {
+    match stmt {
+        Stmt::Item(item) => visitor.visit_item(item),
+        Stmt::Expr(expr) | Stmt::Semi(expr) => visitor.visit_expr(expr),
+    }
+}
+
+pub fn walk_item<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, item: &Item<'a>) {
+    match &item.kind {
+        ItemKind::Struct {
+            backing, fields, ..
+        } => {
+            if let Some(backing) = backing {
+                visitor.visit_expr(backing);
+            }
+            for field in fields {
+                visitor.visit_expr(&field.ty);
+            }
+        }
+        ItemKind::Union { fields } => {
+            for field in fields {
+                visitor.visit_expr(&field.ty);
+            }
+        }
+        ItemKind::Enum { backing, variants } => {
+            if let Some(backing) = backing {
+                visitor.visit_expr(backing);
+            }
+            for variant in variants {
+                if let Some(ty) = &variant.ty {
+                    visitor.visit_expr(ty);
+                }
+                if let Some(value) = &variant.value {
+                    visitor.visit_expr(value);
+                }
+            }
+        }
+        ItemKind::Fn { params, ret, body } => {
+            for param in params {
+                visitor.visit_expr(&param.ty);
+            }
+            if let Some(ret) = ret {
+                visitor.visit_expr(ret);
+            }
+            visitor.visit_expr(body);
+        }
+        ItemKind::Const(value) => visitor.visit_expr(value),
+    }
+}
+
+#[cfg(test)]
+mod tests_visitor {
+    use crate::{
+        lexer::lex,
+        parser::{parse, parse_expr, Expr},
+        visit::{walk_expr, Visitor},
+    };
+
+    #[derive(Default)]
+    struct CountNums(usize);
+
+    impl<'a> Visitor<'a> for CountNums {
+        fn visit_expr(&mut self, expr: &Expr<'a>) {
+            if let Expr::Num(_) = expr {
+                self.0 += 1;
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    #[test]
+    fn count_nums() {
+        let tokens = lex("test", "f(1, [2, x[3]]) + -4 ** y.z");
+        let mut counter = CountNums::default();
+        counter.visit_expr(&parse_expr(&tokens).unwrap());
+        assert_eq!(counter.0, 4);
+    }
+
+    #[test]
+    fn through_items() {
+        let tokens = lex(
+            "test",
+            "E :: enum u8 { A = 1, B }\nf :: fn(a: u8) -> if a then 5 else { 6; 7 };\n",
+        );
+        let mut counter = CountNums::default();
+        for stmt in parse(&tokens).unwrap() {
+            counter.visit_stmt(&stmt);
+        }
+        assert_eq!(counter.0, 4);
+    }
+}
@@ -0,0 +1,46 @@
+//! Lexes every `.sus` file in `tests/corpus` and compares the tokens against
+//! the `.tokens` golden file next to it. Run with `UPDATE_GOLDENS=1` to
+//! rewrite the goldens from the current lexer output.
+
+use std::{env, fs, path::Path};
+
+use csussus::lexer::lex;
+
+#[test]
+fn corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let update = env::var_os("UPDATE_GOLDENS").is_some();
+
+    let mut sources: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sus"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "no .sus files in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for source in &sources {
+        let name = source.file_name().unwrap().to_str().unwrap();
+        let code = fs::read_to_string(source).unwrap();
+        let actual = lex(name, &code).to_string();
+        let golden = source.with_extension("tokens");
+
+        if update {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+
+        match fs::read_to_string(&golden) {
+            Ok(expected) if expected == actual => {}
+            Ok(_) => failures.push(format!("{name}: tokens differ from golden")),
+            Err(_) => failures.push(format!("{name}: missing {}", golden.display())),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with UPDATE_GOLDENS=1 to regenerate",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,29 @@
+use std::process::Command;
+
+#[test]
+fn count_token_types() {
+    let output = Command::new(env!("CARGO_BIN_EXE_csussus"))
+        .arg("--count")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/corpus/misc.sus"
+        ))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let count = |name: &str| -> usize {
+        let line = stdout
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some(name))
+            .unwrap_or_else(|| panic!("no count for {name}"));
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    };
+
+    assert_eq!(count("Semi"), 19);
+    assert_eq!(count("Loop"), 1);
+    assert_eq!(count("Total"), 145);
+    // types are listed in declaration order
+    assert!(stdout.find("And").unwrap() < stdout.find("Semi").unwrap());
+}
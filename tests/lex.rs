@@ -0,0 +1,9 @@
+use csussus::lexer::{lex, TokenType};
+
+#[test]
+fn lex_from_library() {
+    let tokens = lex("test", "add :: fn(a : i32) >- i32 -> a + 1;");
+    assert_eq!(tokens.types.len(), 16);
+    assert_eq!(tokens.types[3], TokenType::Fn);
+    assert_eq!(tokens.spans[0].slice, "add");
+}
@@ -0,0 +1,27 @@
+use std::process::Command;
+
+#[test]
+fn precedence_column() {
+    let output = Command::new(env!("CARGO_BIN_EXE_csussus"))
+        .arg("--prec")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/corpus/misc.sus"
+        ))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let row = |name: &str| -> Vec<&str> {
+        stdout
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .find(|row| row[1] == name)
+            .unwrap_or_else(|| panic!("no {name} token"))
+    };
+
+    assert_eq!(row("Plus")[2..], ["9", "+"]);
+    let ident = row("Ident");
+    assert_eq!(ident.len(), 3);
+}